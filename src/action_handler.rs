@@ -0,0 +1,15 @@
+use async_trait::async_trait;
+
+/// Handler for [`Action::Custom`](crate::config::Action::Custom) actions
+/// registered under a name with
+/// [`StateMachine::with_action_handler`](crate::state_machine::StateMachine::with_action_handler).
+#[async_trait]
+pub trait CustomActionHandler: Send + Sync {
+    /// Runs the action with its configured `params` and the current response
+    /// buffer. Returning `Some` appends the value to the next buffer.
+    async fn handle(
+        &self,
+        params: &serde_json::Value,
+        response_buffer: &[String],
+    ) -> Result<Option<String>, anyhow::Error>;
+}
@@ -0,0 +1,281 @@
+//! A running machine driven by commands, for embedders that feed it input
+//! and watch it over time rather than awaiting a single `run`.
+//!
+//! [`spawn`] starts the machine in a task and returns an [`ActorHandle`].
+//! Handles are cheap to clone, so any number of producers can send
+//! commands, and each [`subscribe`](ActorHandle::subscribe)r receives every
+//! [`ActorEvent`] from then on.
+
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, mpsc, oneshot};
+
+use crate::config::Config;
+use crate::observer::StateMachineObserver;
+use crate::state_machine::{RunState, RunStatus, StateMachine};
+
+/// Capacity of the input, output and event channels.
+const CHANNEL_CAPACITY: usize = 100;
+
+#[derive(Debug)]
+pub enum ActorCommand {
+    /// Sends an input to the machine's WaitForInput actions.
+    PushInput(String),
+    /// Replies with a snapshot of the run's progress.
+    GetState(oneshot::Sender<RunState>),
+    /// Replaces the config once the current state finishes, like
+    /// [`StateMachine::get_config_update_tx`].
+    Reload(Box<Config>),
+    /// Stops the run before its next state, cancelling pending waits.
+    Shutdown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActorEvent {
+    /// A state is about to run its actions.
+    StateEntered(String),
+    /// A Yield without a stream sent this.
+    Output(String),
+    /// The run ended. The actor keeps answering `GetState` until every
+    /// handle is dropped.
+    Finished {
+        status: RunStatus,
+        response_buffer: Vec<String>,
+    },
+    /// The run stopped with an error.
+    Failed(String),
+}
+
+/// Sends commands to a spawned machine and subscribes to its events.
+#[derive(Debug, Clone)]
+pub struct ActorHandle {
+    commands: mpsc::Sender<ActorCommand>,
+    events: broadcast::Sender<ActorEvent>,
+}
+
+impl ActorHandle {
+    /// Events sent from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<ActorEvent> {
+        self.events.subscribe()
+    }
+
+    /// Fails only if the actor has stopped.
+    pub async fn send(&self, command: ActorCommand) -> Result<(), anyhow::Error> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| anyhow::anyhow!("actor stopped"))
+    }
+
+    pub async fn push_input(&self, input: impl Into<String>) -> Result<(), anyhow::Error> {
+        self.send(ActorCommand::PushInput(input.into())).await
+    }
+
+    pub async fn get_state(&self) -> Result<RunState, anyhow::Error> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send(ActorCommand::GetState(reply_tx)).await?;
+        reply_rx.await.map_err(|_| anyhow::anyhow!("actor stopped"))
+    }
+
+    pub async fn reload(&self, config: Config) -> Result<(), anyhow::Error> {
+        self.send(ActorCommand::Reload(Box::new(config))).await
+    }
+
+    pub async fn shutdown(&self) -> Result<(), anyhow::Error> {
+        self.send(ActorCommand::Shutdown).await
+    }
+}
+
+/// Forwards state entries to the event channel.
+struct EventObserver(broadcast::Sender<ActorEvent>);
+
+impl StateMachineObserver for EventObserver {
+    fn on_state_enter(&self, state_key: &str) {
+        // no subscribers is not an error
+        let _ = self.0.send(ActorEvent::StateEntered(state_key.to_string()));
+    }
+}
+
+/// Runs `machine` from its initial state in a new task, connecting its
+/// input and output to the returned handle. The machine's own input and
+/// output channels, if any, are replaced.
+pub fn spawn(machine: StateMachine) -> ActorHandle {
+    let (commands_tx, mut commands) = mpsc::channel(CHANNEL_CAPACITY);
+    let (events, _) = broadcast::channel(CHANNEL_CAPACITY);
+    let (input_tx, input_rx) = broadcast::channel(CHANNEL_CAPACITY);
+    let (output_tx, mut output_rx) = broadcast::channel(CHANNEL_CAPACITY);
+
+    let mut machine = machine.with_observer(Arc::new(EventObserver(events.clone())));
+    machine.connect(input_rx, output_tx);
+    let run_state = machine.peek();
+    let shutdown = machine.shutdown_token();
+    let config_update_tx = machine.get_config_update_tx();
+
+    let task_events = events.clone();
+    tokio::spawn(async move {
+        let events = task_events;
+        let run = machine.run_with_status(Vec::new());
+        tokio::pin!(run);
+        let finished = loop {
+            tokio::select! {
+                // forward outputs before anything they might precede
+                biased;
+                Ok(output) = output_rx.recv() => {
+                    let _ = events.send(ActorEvent::Output(output));
+                }
+                result = &mut run => break result,
+                Some(command) = commands.recv() => match command {
+                    ActorCommand::PushInput(input) => {
+                        // the machine keeps its receiver for the whole run
+                        let _ = input_tx.send(input);
+                    }
+                    ActorCommand::GetState(reply) => {
+                        let _ = reply.send(run_state.lock().unwrap().clone());
+                    }
+                    ActorCommand::Reload(config) => {
+                        if config_update_tx.send(*config).await.is_err() {
+                            tracing::warn!("machine stopped before the reload");
+                        }
+                    }
+                    ActorCommand::Shutdown => shutdown.cancel(),
+                },
+            }
+        };
+        while let Ok(output) = output_rx.try_recv() {
+            let _ = events.send(ActorEvent::Output(output));
+        }
+        let _ = events.send(match finished {
+            Ok((status, response_buffer)) => ActorEvent::Finished {
+                status,
+                response_buffer,
+            },
+            Err(e) => ActorEvent::Failed(format!("{:#}", e)),
+        });
+
+        while let Some(command) = commands.recv().await {
+            match command {
+                ActorCommand::GetState(reply) => {
+                    let _ = reply.send(run_state.lock().unwrap().clone());
+                }
+                command => tracing::debug!(?command, "run finished, ignoring command"),
+            }
+        }
+    });
+
+    ActorHandle {
+        commands: commands_tx,
+        events,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Action, AgentConfig};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    /// Waits for an input, then yields it; `repeat` loops back to waiting.
+    fn echo_config(repeat: bool) -> Config {
+        let state = |action: Action, next_state: Option<&str>| AgentConfig {
+            actions: vec![action.into()],
+            next_state: next_state.map(str::to_string),
+            ..Default::default()
+        };
+        Config {
+            label: "echo".to_string(),
+            initial_state_key: "wait".to_string(),
+            states: HashMap::from([
+                (
+                    "wait".to_string(),
+                    state(Action::WaitForInput(None), Some("reply")),
+                ),
+                (
+                    "reply".to_string(),
+                    state(Action::Yield(None), repeat.then_some("wait")),
+                ),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    async fn next_event(events: &mut broadcast::Receiver<ActorEvent>) -> ActorEvent {
+        tokio::time::timeout(Duration::from_secs(2), events.recv())
+            .await
+            .expect("no event")
+            .unwrap()
+    }
+
+    /// The next event other than a state entry.
+    async fn next_non_state_event(events: &mut broadcast::Receiver<ActorEvent>) -> ActorEvent {
+        loop {
+            match next_event(events).await {
+                ActorEvent::StateEntered(_) => continue,
+                event => return event,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_actor_echoes_inputs_and_reloads() {
+        let handle = spawn(StateMachine::new_with_config(echo_config(true)).unwrap());
+        let mut events = handle.subscribe();
+
+        handle.push_input("hello").await.unwrap();
+        assert_eq!(
+            next_non_state_event(&mut events).await,
+            ActorEvent::Output("hello".to_string())
+        );
+
+        // the reload applies once the pending wait finishes, restarting at
+        // the new config's wait
+        handle.reload(echo_config(false)).await.unwrap();
+        handle.push_input("again").await.unwrap();
+        handle.push_input("reloaded").await.unwrap();
+        assert_eq!(
+            next_non_state_event(&mut events).await,
+            ActorEvent::Output("reloaded".to_string())
+        );
+        assert_eq!(
+            next_non_state_event(&mut events).await,
+            ActorEvent::Finished {
+                status: RunStatus::Completed,
+                // Yield produces no output
+                response_buffer: Vec::new(),
+            }
+        );
+
+        let state = handle.get_state().await.unwrap();
+        assert_eq!(state.state_key.as_deref(), Some("reply"));
+        assert_eq!(state.status, Some(RunStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn test_actor_reports_states_and_shuts_down() {
+        let handle = spawn(StateMachine::new_with_config(echo_config(true)).unwrap());
+        let mut events = handle.subscribe();
+        let producer = handle.clone();
+
+        assert_eq!(
+            next_event(&mut events).await,
+            ActorEvent::StateEntered("wait".to_string())
+        );
+        let state = handle.get_state().await.unwrap();
+        assert_eq!(state.state_key.as_deref(), Some("wait"));
+        assert_eq!(state.status, None);
+
+        producer.shutdown().await.unwrap();
+        let event = next_non_state_event(&mut events).await;
+        assert!(
+            matches!(
+                event,
+                ActorEvent::Finished {
+                    status: RunStatus::ShutdownRequested,
+                    ..
+                }
+            ),
+            "{:?}",
+            event
+        );
+    }
+}
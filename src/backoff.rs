@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Exponential backoff with optional full jitter, shared by every retry site.
+///
+/// The un-jittered delay for attempt `n` is `base_ms * multiplier^n`, capped at
+/// `max_ms`. With `jitter` enabled each delay is drawn uniformly from
+/// `[0, computed]` so that concurrent retries don't synchronize.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Backoff {
+    #[serde(default = "default_base_ms")]
+    pub base_ms: u64,
+    #[serde(default = "default_max_ms")]
+    pub max_ms: u64,
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+    #[serde(default = "default_jitter")]
+    pub jitter: bool,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            base_ms: default_base_ms(),
+            max_ms: default_max_ms(),
+            multiplier: default_multiplier(),
+            jitter: default_jitter(),
+        }
+    }
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration) -> Self {
+        Self {
+            base_ms: base.as_millis() as u64,
+            max_ms: max.as_millis() as u64,
+            ..Default::default()
+        }
+    }
+
+    /// The capped delay for `attempt` (zero-based) before jitter is applied.
+    pub fn computed_delay(&self, attempt: u32) -> Duration {
+        let exponent = i32::try_from(attempt).unwrap_or(i32::MAX);
+        let delay_ms = (self.base_ms as f64) * self.multiplier.powi(exponent);
+        Duration::from_millis(delay_ms.min(self.max_ms as f64) as u64)
+    }
+
+    /// The delay to wait before retry `attempt` (zero-based).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let computed = self.computed_delay(attempt);
+        if !self.jitter || computed.is_zero() {
+            return computed;
+        }
+        Duration::from_millis(rand::random_range(0..=computed.as_millis() as u64))
+    }
+
+    /// An endless sequence of delays, one per retry attempt.
+    pub fn delays(&self) -> impl Iterator<Item = Duration> + '_ {
+        (0..).map(|attempt| self.delay(attempt))
+    }
+}
+
+fn default_base_ms() -> u64 {
+    100
+}
+
+fn default_max_ms() -> u64 {
+    10_000
+}
+
+fn default_multiplier() -> f64 {
+    2.0
+}
+
+fn default_jitter() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_computed_delay_sequence() {
+        let backoff = Backoff {
+            base_ms: 100,
+            max_ms: 1_000,
+            multiplier: 2.0,
+            jitter: false,
+        };
+        let delays: Vec<u64> = backoff
+            .delays()
+            .take(6)
+            .map(|delay| delay.as_millis() as u64)
+            .collect();
+        assert_eq!(delays, vec![100, 200, 400, 800, 1_000, 1_000]);
+        assert_eq!(
+            backoff.computed_delay(u32::MAX),
+            Duration::from_millis(1_000)
+        );
+    }
+
+    #[test]
+    fn test_jitter_within_bounds() {
+        let backoff = Backoff::new(Duration::from_millis(50), Duration::from_millis(2_000));
+        for attempt in 0..10 {
+            let computed = backoff.computed_delay(attempt);
+            for _ in 0..100 {
+                assert!(backoff.delay(attempt) <= computed);
+            }
+        }
+    }
+}
@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+
+use crate::clock::{Clock, TokioClock};
+use crate::models::YieldBatch;
+
+/// Messages from batching Yields, held per destination until a batch is
+/// due and then sent as one message: a JSON array of the messages in the
+/// order they were yielded.
+///
+/// Destinations are named streams, or `None` for the machine's output
+/// channel. Each machine has its own batcher and flushes it when its run
+/// finishes.
+#[derive(Debug)]
+pub struct YieldBatcher {
+    pending: Arc<Mutex<HashMap<Option<String>, Pending>>>,
+    next_id: AtomicU64,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for YieldBatcher {
+    fn default() -> Self {
+        Self {
+            pending: Arc::default(),
+            next_id: AtomicU64::new(0),
+            clock: Arc::new(TokioClock),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Pending {
+    // tells a batch's timer whether the batch it was started for is still
+    // the one pending
+    id: u64,
+    messages: Vec<String>,
+    output_tx: broadcast::Sender<String>,
+}
+
+impl Pending {
+    fn send(&self, messages: &[String]) -> Result<(), anyhow::Error> {
+        self.output_tx.send(serde_json::to_string(messages)?)?;
+        Ok(())
+    }
+}
+
+impl YieldBatcher {
+    /// Times batches' `max_delay_ms` on `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Adds `messages` for `destination`, sending a batch for every
+    /// `max_messages` pending. The first message of a batch starts its
+    /// `max_delay_ms` timer, which sends whatever is pending when it fires.
+    pub fn push(
+        &self,
+        destination: Option<&str>,
+        output_tx: &broadcast::Sender<String>,
+        messages: Vec<String>,
+        batch: &YieldBatch,
+    ) -> Result<(), anyhow::Error> {
+        let destination = destination.map(str::to_string);
+        let mut pending = self.pending.lock().unwrap();
+        let entry = pending.entry(destination.clone()).or_insert_with(|| {
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            if let Some(max_delay_ms) = batch.max_delay_ms {
+                self.start_timer(destination.clone(), id, max_delay_ms);
+            }
+            Pending {
+                id,
+                messages: Vec::new(),
+                output_tx: output_tx.clone(),
+            }
+        });
+        entry.messages.extend(messages);
+
+        let size = batch.max_messages.unwrap_or(usize::MAX).max(1);
+        let mut result = Ok(());
+        while entry.messages.len() >= size && result.is_ok() {
+            let due: Vec<String> = entry.messages.drain(..size).collect();
+            result = entry.send(&due);
+        }
+        if entry.messages.is_empty() {
+            pending.remove(&destination);
+        }
+        result
+    }
+
+    /// Sends what is pending for `destination`, so an unbatched Yield
+    /// doesn't overtake it.
+    pub fn flush(&self, destination: Option<&str>) -> Result<(), anyhow::Error> {
+        let pending = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&destination.map(str::to_string));
+        match pending {
+            Some(pending) => pending.send(&pending.messages),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends everything pending, logging destinations nobody listens to.
+    pub fn flush_all(&self) {
+        let pending = std::mem::take(&mut *self.pending.lock().unwrap());
+        for (destination, pending) in pending {
+            if let Err(e) = pending.send(&pending.messages) {
+                tracing::warn!(?destination, error = %e, "failed to flush yielded messages");
+            }
+        }
+    }
+
+    fn start_timer(&self, destination: Option<String>, id: u64, max_delay_ms: u64) {
+        let pending = self.pending.clone();
+        let delay = self.clock.sleep(Duration::from_millis(max_delay_ms));
+        tokio::spawn(async move {
+            delay.await;
+            let mut pending = pending.lock().unwrap();
+            if pending
+                .get(&destination)
+                .is_some_and(|entry| entry.id == id)
+            {
+                let entry = pending.remove(&destination).unwrap();
+                // the run may have finished without anyone listening
+                let _ = entry.send(&entry.messages);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Action, AgentConfig, Config};
+    use crate::models::YieldData;
+    use crate::state_machine::StateMachine;
+
+    fn received(output_rx: &mut broadcast::Receiver<String>) -> Vec<String> {
+        std::iter::from_fn(|| output_rx.try_recv().ok()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_sent_when_full() {
+        let batcher = YieldBatcher::default();
+        let (output_tx, mut output_rx) = broadcast::channel(10);
+        let batch = YieldBatch {
+            max_messages: Some(2),
+            max_delay_ms: None,
+        };
+        let messages = |messages: &[&str]| messages.iter().map(|m| m.to_string()).collect();
+
+        batcher
+            .push(None, &output_tx, messages(&["a", "b", "c"]), &batch)
+            .unwrap();
+        assert_eq!(received(&mut output_rx), [r#"["a","b"]"#]);
+        batcher
+            .push(None, &output_tx, messages(&["d"]), &batch)
+            .unwrap();
+        batcher
+            .push(None, &output_tx, messages(&["e"]), &batch)
+            .unwrap();
+        assert_eq!(received(&mut output_rx), [r#"["c","d"]"#]);
+
+        batcher.flush(None).unwrap();
+        assert_eq!(received(&mut output_rx), [r#"["e"]"#]);
+        batcher.flush_all();
+        assert!(received(&mut output_rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_sent_after_max_delay() {
+        let batcher = YieldBatcher::default();
+        let (output_tx, mut output_rx) = broadcast::channel(10);
+        let batch = YieldBatch {
+            max_messages: None,
+            max_delay_ms: Some(20),
+        };
+
+        batcher
+            .push(Some("events"), &output_tx, vec!["a".to_string()], &batch)
+            .unwrap();
+        assert!(received(&mut output_rx).is_empty());
+        let message = tokio::time::timeout(Duration::from_secs(1), output_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message, r#"["a"]"#);
+    }
+
+    #[tokio::test]
+    async fn test_machine_flushes_batched_yields_before_returning() {
+        let yield_all = Action::Yield(Some(YieldData {
+            split: true,
+            batch: Some(YieldBatch {
+                max_messages: Some(2),
+                max_delay_ms: None,
+            }),
+            ..Default::default()
+        }));
+        let config = Config {
+            label: "chatty".to_string(),
+            initial_state_key: "chat".to_string(),
+            states: HashMap::from([(
+                "chat".to_string(),
+                AgentConfig {
+                    actions: vec![yield_all.into()],
+                    next_state: None,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let (input_tx, input_rx) = broadcast::channel(10);
+        let (output_tx, mut output_rx) = broadcast::channel(10);
+        let mut machine = StateMachine::new_with_config(config).unwrap();
+        machine.connect(input_rx, output_tx);
+        drop(input_tx);
+
+        machine
+            .run_with_input(vec![r#"["1","2","3","4","5"]"#.to_string()])
+            .await
+            .unwrap();
+        // five messages, coalesced into three sends, the last on completion
+        assert_eq!(
+            received(&mut output_rx),
+            [r#"["1","2"]"#, r#"["3","4"]"#, r#"["5"]"#]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::clock::{Clock, TokioClock};
+use crate::config::ResponseCacheConfig;
+use crate::models::HttpMethod;
+
+/// CallApi responses kept for the configured TTL, shared by a machine
+/// and the agents it spawns.
+///
+/// Keys are built by the caller from everything that distinguishes one
+/// response from another. Expired entries are dropped when they are next
+/// looked up and whenever a response is stored.
+#[derive(Debug)]
+pub struct ResponseCache {
+    config: Option<ResponseCacheConfig>,
+    entries: Mutex<HashMap<String, Entry>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for ResponseCache {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+/// A cached response: its status code, headers and checked body.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedResponse {
+    pub status: u16,
+    /// By lowercase name, as [`join_headers`](crate::http::join_headers)
+    /// returns them, with sensitive ones masked.
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    response: CachedResponse,
+    expires_at: Instant,
+}
+
+impl ResponseCache {
+    /// A cache storing nothing without a config.
+    pub fn new(config: Option<ResponseCacheConfig>) -> Self {
+        Self {
+            config,
+            entries: Mutex::default(),
+            clock: Arc::new(TokioClock),
+        }
+    }
+
+    /// Expires entries by `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Whether responses to `method` requests are cached.
+    pub fn caches(&self, method: &HttpMethod) -> bool {
+        self.config
+            .as_ref()
+            .is_some_and(|config| config.methods.contains(method))
+    }
+
+    /// The response stored under `key`, unless it has expired.
+    pub fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.expires_at > self.clock.now() => Some(entry.response.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, key: String, response: CachedResponse) {
+        let Some(config) = &self.config else {
+            return;
+        };
+        let now = self.clock.now();
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires_at > now);
+        entries.insert(
+            key,
+            Entry {
+                response,
+                expires_at: now + Duration::from_millis(config.ttl_ms),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::TestClock;
+
+    fn cache(ttl_ms: u64) -> ResponseCache {
+        ResponseCache::new(Some(ResponseCacheConfig {
+            ttl_ms,
+            methods: vec![HttpMethod::GET],
+        }))
+    }
+
+    fn sunny() -> CachedResponse {
+        CachedResponse {
+            status: 200,
+            headers: BTreeMap::new(),
+            body: "sunny".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entries_expire_after_ttl() {
+        let clock = Arc::new(TestClock::start());
+        let cache = cache(50).with_clock(clock.clone());
+        cache.insert("GET /weather".to_string(), sunny());
+        assert_eq!(cache.get("GET /weather"), Some(sunny()));
+        assert_eq!(cache.get("GET /forecast"), None);
+
+        clock.advance(Duration::from_millis(49)).await;
+        assert_eq!(cache.get("GET /weather"), Some(sunny()));
+        clock.advance(Duration::from_millis(1)).await;
+        assert_eq!(cache.get("GET /weather"), None);
+    }
+
+    #[test]
+    fn test_caches_configured_methods_only() {
+        assert!(cache(1_000).caches(&HttpMethod::GET));
+        assert!(!cache(1_000).caches(&HttpMethod::POST));
+
+        let disabled = ResponseCache::new(None);
+        assert!(!disabled.caches(&HttpMethod::GET));
+        disabled.insert("GET /weather".to_string(), sunny());
+        assert_eq!(disabled.get("GET /weather"), None);
+    }
+}
@@ -0,0 +1,260 @@
+//! Saving a run's progress so a run that was stopped, or whose process
+//! died, can continue where it left off.
+//!
+//! A machine given a [`CheckpointStore`] with
+//! [`StateMachine::with_checkpoint_store`](crate::state_machine::StateMachine::with_checkpoint_store)
+//! saves a [`CheckpointData`] under its run ID after every transition and
+//! once more when the run ends. A later machine built with the same
+//! [run ID](crate::state_machine::StateMachine::with_run_id) and store
+//! resumes from the saved state instead of the initial one. Actions of the
+//! state that was running when the run stopped run again.
+//!
+//! [`FileCheckpointStore`] keeps one JSON file per run; other backends, such
+//! as Redis or S3, implement the trait themselves.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Context as _;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::state_machine::RunStatus;
+
+/// A run's progress between two states.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointData {
+    /// The state to run next.
+    pub next_state_key: String,
+    pub response_buffer: Vec<String>,
+    /// States started so far, counted against `max_iterations`.
+    pub iterations: u64,
+    /// Whether an error was routed to the dead-letter state.
+    pub dead_lettered: bool,
+    /// Outputs stored under an action's `output_name`, and variables such as
+    /// `last_status`.
+    pub variables: HashMap<String, String>,
+    /// Outputs of the latest run of each state, for `State` placeholders.
+    pub state_outputs: HashMap<String, String>,
+    /// How the run ended, once it has. Resuming a finished run returns its
+    /// result without running any state.
+    pub status: Option<RunStatus>,
+}
+
+/// Where checkpoints are kept, keyed by run ID.
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    /// Replaces the checkpoint saved under `key`.
+    async fn save(&self, key: &str, data: &CheckpointData) -> Result<(), anyhow::Error>;
+
+    /// The checkpoint saved under `key`, if there is one.
+    async fn load(&self, key: &str) -> Result<Option<CheckpointData>, anyhow::Error>;
+}
+
+/// Keeps each checkpoint as `<key>.json` in a directory, created when the
+/// first checkpoint is saved.
+#[derive(Debug, Clone)]
+pub struct FileCheckpointStore {
+    dir: PathBuf,
+}
+
+impl FileCheckpointStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, anyhow::Error> {
+        // keys name files directly, so they must not reach outside the
+        // directory
+        if key.is_empty() || key.starts_with('.') || key.contains(['/', '\\']) {
+            anyhow::bail!("invalid checkpoint key {:?}", key);
+        }
+        Ok(self.dir.join(format!("{}.json", key)))
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn save(&self, key: &str, data: &CheckpointData) -> Result<(), anyhow::Error> {
+        let path = self.path(key)?;
+        tokio::fs::create_dir_all(&self.dir)
+            .await
+            .with_context(|| format!("failed to create {}", self.dir.display()))?;
+        // written beside the checkpoint and renamed over it, so a crash
+        // mid-write leaves the previous checkpoint intact
+        let partial = path.with_extension("json.partial");
+        tokio::fs::write(&partial, serde_json::to_vec(data)?)
+            .await
+            .with_context(|| format!("failed to write {}", partial.display()))?;
+        tokio::fs::rename(&partial, &path)
+            .await
+            .with_context(|| format!("failed to replace {}", path.display()))?;
+        Ok(())
+    }
+
+    async fn load(&self, key: &str) -> Result<Option<CheckpointData>, anyhow::Error> {
+        let path = self.path(key)?;
+        let contents = match tokio::fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to read {}", path.display()));
+            }
+        };
+        let data = serde_json::from_slice(&contents)
+            .with_context(|| format!("invalid checkpoint {}", path.display()))?;
+        Ok(Some(data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Action, ActionConfig, AgentConfig, Config};
+    use crate::state_machine::StateMachine;
+    use std::sync::{Arc, Mutex};
+
+    /// Keeps every checkpoint saved, newest last.
+    #[derive(Default)]
+    struct MemoryStore(Mutex<HashMap<String, Vec<CheckpointData>>>);
+
+    #[async_trait]
+    impl CheckpointStore for MemoryStore {
+        async fn save(&self, key: &str, data: &CheckpointData) -> Result<(), anyhow::Error> {
+            let mut saved = self.0.lock().unwrap();
+            saved.entry(key.to_string()).or_default().push(data.clone());
+            Ok(())
+        }
+
+        async fn load(&self, key: &str) -> Result<Option<CheckpointData>, anyhow::Error> {
+            let saved = self.0.lock().unwrap();
+            Ok(saved.get(key).and_then(|saved| saved.last()).cloned())
+        }
+    }
+
+    /// `a`, then `b`, then `c`, each appending to its input; `a`'s output is
+    /// stored as `x`.
+    fn three_states() -> Config {
+        let state = |output: &str, next_state: Option<&str>| AgentConfig {
+            actions: vec![ActionConfig {
+                output_name: (output == "a").then(|| "x".to_string()),
+                ..Action::Delay {
+                    duration_ms: 0,
+                    output: Some(output.to_string()),
+                }
+                .into()
+            }],
+            next_state: next_state.map(str::to_string),
+            ..Default::default()
+        };
+        Config {
+            label: "steps".to_string(),
+            initial_state_key: "a".to_string(),
+            states: HashMap::from([
+                ("a".to_string(), state("a", Some("b"))),
+                ("b".to_string(), state("{Input}b", Some("c"))),
+                ("c".to_string(), state(r#"{Input}c{"Named":"x"}"#, None)),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    fn machine(store: &Arc<MemoryStore>) -> StateMachine {
+        StateMachine::new_with_config(three_states())
+            .unwrap()
+            .with_run_id("run-1")
+            .with_checkpoint_store(store.clone())
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_saved_on_each_transition() {
+        let store = Arc::new(MemoryStore::default());
+        assert_eq!(machine(&store).run().await.unwrap(), ["abca"]);
+
+        let saved = store.0.lock().unwrap()["run-1"].clone();
+        let progress: Vec<_> = saved
+            .iter()
+            .map(|checkpoint| {
+                (
+                    checkpoint.next_state_key.as_str(),
+                    checkpoint.response_buffer.clone(),
+                    checkpoint.status,
+                )
+            })
+            .collect();
+        assert_eq!(
+            progress,
+            [
+                ("b", vec!["a".to_string()], None),
+                ("c", vec!["ab".to_string()], None),
+                ("c", vec!["abca".to_string()], Some(RunStatus::Completed)),
+            ]
+        );
+        assert_eq!(saved[0].variables["x"], "a");
+        assert_eq!(saved[1].iterations, 2);
+
+        // a finished run is not run again
+        assert_eq!(machine(&store).run().await.unwrap(), ["abca"]);
+        assert_eq!(store.0.lock().unwrap()["run-1"].len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_run_resumes_from_stored_checkpoint() {
+        let store = Arc::new(MemoryStore::default());
+        let checkpoint = CheckpointData {
+            next_state_key: "b".to_string(),
+            response_buffer: vec!["z".to_string()],
+            iterations: 1,
+            dead_lettered: false,
+            variables: HashMap::from([("x".to_string(), "y".to_string())]),
+            state_outputs: HashMap::new(),
+            status: None,
+        };
+        store.save("run-1", &checkpoint).await.unwrap();
+
+        // `a` doesn't run again, and its stored output is restored
+        assert_eq!(machine(&store).run().await.unwrap(), ["zbcy"]);
+        let saved = store.0.lock().unwrap()["run-1"].clone();
+        assert_eq!(saved.last().unwrap().iterations, 3);
+
+        // other runs start over
+        let output = StateMachine::new_with_config(three_states())
+            .unwrap()
+            .with_checkpoint_store(store.clone())
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(output, ["abca"]);
+    }
+
+    #[tokio::test]
+    async fn test_file_store_round_trips_checkpoints() {
+        let dir = std::env::temp_dir().join("dsm_test_file_checkpoints");
+        let _ = std::fs::remove_dir_all(&dir);
+        let store = FileCheckpointStore::new(&dir);
+        assert_eq!(store.load("run-1").await.unwrap(), None);
+
+        let checkpoint = CheckpointData {
+            next_state_key: "b".to_string(),
+            response_buffer: vec!["a".to_string()],
+            iterations: 1,
+            dead_lettered: false,
+            variables: HashMap::new(),
+            state_outputs: HashMap::from([("a".to_string(), "a".to_string())]),
+            status: None,
+        };
+        store.save("run-1", &checkpoint).await.unwrap();
+        let finished = CheckpointData {
+            status: Some(RunStatus::Completed),
+            ..checkpoint
+        };
+        store.save("run-1", &finished).await.unwrap();
+        assert_eq!(store.load("run-1").await.unwrap(), Some(finished.clone()));
+        assert!(dir.join("run-1.json").exists());
+
+        for key in ["", "../run-1", "a/b", ".hidden"] {
+            assert!(store.save(key, &finished).await.is_err(), "{:?}", key);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
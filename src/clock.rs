@@ -0,0 +1,53 @@
+//! The clock every time-dependent part of the engine reads: Delay and the
+//! other waits, timeouts, retry and restart backoff, rate limiting, cache
+//! TTLs, yield batching and the deadlock watchdog.
+//!
+//! Machines use [`TokioClock`] unless given another with
+//! [`StateMachine::with_clock`](crate::state_machine::StateMachine::with_clock).
+//! Since it follows Tokio's clock, tests can pause and advance it, as
+//! `TestClock` in [`test_utils`](crate::test_utils) does, to run time-based
+//! configs without sleeping. Timestamps in run reports and input metadata
+//! stay on the system clock.
+
+use std::future::Future;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::time::Instant;
+
+/// A source of the current instant and of sleeps measured against it.
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> Instant;
+
+    /// Finishes once `duration` has passed on this clock.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Tokio's clock: real time, unless the runtime's clock is paused.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// Runs `future` until it finishes or `duration` passes on `clock`, like
+/// [`tokio::time::timeout`]. Returns `None` on a timeout.
+pub(crate) async fn timeout<F: Future>(
+    clock: &dyn Clock,
+    duration: Duration,
+    future: F,
+) -> Option<F::Output> {
+    tokio::select! {
+        // a future that is ready wins over an elapsed timeout
+        biased;
+        output = future => Some(output),
+        _ = clock.sleep(duration) => None,
+    }
+}
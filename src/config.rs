@@ -1,34 +1,1259 @@
-use crate::models::{AgentData, CallApiData, LlmData};
+use crate::backoff::Backoff;
+use crate::llm::LlmProviderConfig;
+use crate::models::{
+    AgentConfigSource, AgentData, CallApiData, CallMachineData, HttpMethod, LlmData, MapAgentData,
+    MatchType, MergeStrategy, SseData, WaitForInputData, YieldData,
+};
+use crate::truncate::OutputLimit;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::env;
+use std::sync::LazyLock;
 use strum_macros::EnumDiscriminants;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Config {
     #[serde(alias = "initial_state")]
     pub initial_state_key: String,
+    /// Named alternative starting states, entered with
+    /// [`StateMachine::run_from`](crate::state_machine::StateMachine::run_from).
+    #[serde(default)]
+    pub entry_points: HashMap<String, String>,
     pub label: String,
     pub states: HashMap<String, AgentConfig>,
     pub output_stream: Option<String>,
+    pub webhook: Option<WebhookConfig>,
+    #[serde(default)]
+    pub http: HttpClientConfig,
+    /// State to route to, with the error context as the buffer, when an
+    /// action fails or a transition names a missing state. Without it the
+    /// machine logs failed actions and stops on dangling transitions.
+    pub dead_letter_state: Option<String>,
+    /// Provider used by Llm actions. Without one (and without a provider
+    /// registered through [`StateMachine::with_llm_provider`]) Llm actions
+    /// only log their resolved prompts.
+    ///
+    /// [`StateMachine::with_llm_provider`]: crate::state_machine::StateMachine::with_llm_provider
+    pub llm: Option<LlmProviderConfig>,
+    /// Watchdog that reports when every agent is blocked on WaitForInput.
+    pub deadlock: Option<DeadlockConfig>,
+    #[serde(default)]
+    pub placeholder_delimiters: PlaceholderDelimiters,
+    /// Environment variables checked when the machine is built, so a
+    /// missing secret fails at startup instead of as an empty `Env` value.
+    #[serde(default)]
+    pub env_check: EnvCheck,
+    /// Stops the run after this many states have executed, for configs
+    /// that might loop forever.
+    pub max_iterations: Option<u64>,
+    #[serde(default)]
+    pub buffer_mode: BufferMode,
+    /// In [`BufferMode::Append`], keep only this many of the newest entries.
+    pub max_buffer_entries: Option<usize>,
+    /// Truncates every action's output entering the buffer, unless the
+    /// action sets its own limit.
+    pub max_output: Option<OutputLimit>,
+    /// Directory that relative file paths in the config resolve against:
+    /// agent config files, `body_file`, JSON schema files and token files.
+    /// A relative `base_dir` is itself relative to the config file's
+    /// directory, which is the default.
+    pub base_dir: Option<String>,
+    /// Whether SpawnAgent actions may name the same `output_label`.
+    #[serde(default)]
+    pub duplicate_output_labels: DuplicateOutputLabels,
+    /// What happens to running background agents when a config update
+    /// replaces this config.
+    #[serde(default)]
+    pub reload_drain: ReloadDrain,
+    /// Named CallApi requests, used by CallApi actions here and in inline
+    /// agent configs; see [`parse_config`].
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub templates: HashMap<String, serde_json::Value>,
 }
 
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// How background agents spawned under a config are stopped when a config
+/// update replaces it. Either way their streams are closed and the new
+/// config gets fresh ones.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReloadDrain {
+    /// The agents are aborted right away.
+    #[default]
+    Abort,
+    /// The reload waits for the agents to finish, aborting any still
+    /// running after `timeout_ms`. Without a timeout an agent that never
+    /// finishes blocks the reload forever.
+    Await { timeout_ms: Option<u64> },
+}
+
+/// What loading a config does when several SpawnAgent actions name the same
+/// `output_label`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateOutputLabels {
+    /// The config is rejected, since a repeated label is usually a
+    /// copy-paste mistake.
+    #[default]
+    Error,
+    /// The agents write to one shared stream, interleaving their outputs.
+    Share,
+}
+
+/// How each state's outputs update the response buffer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BufferMode {
+    /// The outputs replace the buffer.
+    #[default]
+    Replace,
+    /// The outputs are appended, building a transcript of the run. Every
+    /// entry is kept until the run ends, so memory grows with the number of
+    /// states run unless `max_buffer_entries` caps it.
+    Append,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct EnvCheck {
+    /// Variables that must be set.
+    #[serde(default)]
+    pub required: Vec<String>,
+    /// Require every variable referenced by an `Env` placeholder to be set.
+    #[serde(default)]
+    pub strict: bool,
+}
+
+/// Markers around placeholders in templates. The default `{` and `}`
+/// collide with literal braces in JSON bodies and prompts, which other
+/// delimiters such as `<<` and `>>` leave untouched. Avoid `${` when loading
+/// with `expand_env`, which expands those tokens first.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlaceholderDelimiters {
+    pub open: String,
+    pub close: String,
+}
+
+impl Default for PlaceholderDelimiters {
+    fn default() -> Self {
+        Self {
+            open: "{".to_string(),
+            close: "}".to_string(),
+        }
+    }
+}
+
+static DEFAULT_PLACEHOLDER_REGEX: LazyLock<Regex> =
+    LazyLock::new(|| PlaceholderDelimiters::default().compile());
+
+impl PlaceholderDelimiters {
+    /// Matches a placeholder, capturing the text between the delimiters.
+    /// Compile it once per config and reuse it; the default delimiters'
+    /// regex is compiled once per process.
+    pub(crate) fn regex(&self) -> Regex {
+        if *self == Self::default() {
+            return DEFAULT_PLACEHOLDER_REGEX.clone();
+        }
+        self.compile()
+    }
+
+    fn compile(&self) -> Regex {
+        Regex::new(&format!(
+            r"(?s){}(.+?){}",
+            regex::escape(&self.open),
+            regex::escape(&self.close)
+        ))
+        .expect("valid regex")
+    }
+
+    /// Whether `text` contains placeholders, so is only known at runtime.
+    pub(crate) fn is_templated(&self, text: &str) -> bool {
+        text.contains(&self.open)
+    }
+}
+
+/// Stall detection for multi-agent topologies.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadlockConfig {
+    /// How long every agent must stay blocked, with no action running,
+    /// before the stall is reported.
+    pub interval_ms: u64,
+    /// Abort the run with an error instead of only logging a warning.
+    #[serde(default)]
+    pub abort: bool,
+}
+
+/// Settings applied when building the machine's shared HTTP client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpClientConfig {
+    /// Transparently decode `Content-Encoding: gzip` responses.
+    #[serde(default = "default_true")]
+    pub gzip: bool,
+    /// Transparently decode `Content-Encoding: br` responses.
+    #[serde(default = "default_true")]
+    pub brotli: bool,
+    /// Transparently decode `Content-Encoding: deflate` responses.
+    #[serde(default = "default_true")]
+    pub deflate: bool,
+    pub tls: Option<TlsConfig>,
+    /// User-Agent sent with every request. Defaults to
+    /// [`DEFAULT_USER_AGENT`](crate::http::DEFAULT_USER_AGENT).
+    pub user_agent: Option<String>,
+    /// Header names whose values are masked in request/response logs, matched
+    /// case-insensitively. A CallApi's `auth_header_name` is always masked.
+    #[serde(default = "default_sensitive_headers")]
+    pub sensitive_headers: Vec<String>,
+    /// Prefix joined onto relative CallApi URLs. Absolute URLs are used
+    /// unchanged.
+    pub base_url: Option<String>,
+    /// Speak HTTP/2 without negotiating it first. Only works against servers
+    /// known to support it, including over plain-text (h2c) connections.
+    #[serde(default)]
+    pub http2_prior_knowledge: bool,
+    /// How long unused pooled connections are kept open.
+    pub pool_idle_timeout_ms: Option<u64>,
+    /// Maximum unused pooled connections kept per host.
+    pub pool_max_idle_per_host: Option<usize>,
+    /// Interval of TCP keepalive probes on open connections.
+    pub tcp_keepalive_ms: Option<u64>,
+    /// Request rate limits keyed by host. A `"*"` entry limits every other
+    /// host, each with its own bucket. Spawned agents share their parent's
+    /// limits.
+    #[serde(default)]
+    pub rate_limits: HashMap<String, RateLimitConfig>,
+    /// Caches CallApi responses. Spawned agents share their parent's cache.
+    pub cache: Option<ResponseCacheConfig>,
+    /// Header sending the machine's run ID with every CallApi request, such
+    /// as `X-Correlation-Id`.
+    pub run_id_header: Option<String>,
+    /// Keeps cookies responses set and sends them back on later requests to
+    /// the same site, for APIs with session logins.
+    #[serde(default)]
+    pub cookies: bool,
+}
+
+/// A token bucket allowing `burst` requests at once, refilled at
+/// `requests_per_second`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    pub requests_per_second: f64,
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+}
+
+fn default_burst() -> u32 {
+    1
+}
+
+/// Successful responses to `methods` requests are reused for `ttl_ms`
+/// instead of being fetched again. Requests are told apart by method, URL,
+/// auth and User-Agent headers and body.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseCacheConfig {
+    pub ttl_ms: u64,
+    #[serde(default = "default_cached_methods")]
+    pub methods: Vec<HttpMethod>,
+}
+
+fn default_cached_methods() -> Vec<HttpMethod> {
+    vec![HttpMethod::GET]
+}
+
+impl Default for HttpClientConfig {
+    fn default() -> Self {
+        Self {
+            gzip: true,
+            brotli: true,
+            deflate: true,
+            tls: None,
+            user_agent: None,
+            sensitive_headers: default_sensitive_headers(),
+            base_url: None,
+            http2_prior_knowledge: false,
+            pool_idle_timeout_ms: None,
+            pool_max_idle_per_host: None,
+            tcp_keepalive_ms: None,
+            rate_limits: HashMap::new(),
+            cache: None,
+            run_id_header: None,
+            cookies: false,
+        }
+    }
+}
+
+fn default_sensitive_headers() -> Vec<String> {
+    ["Authorization", "Cookie", "Set-Cookie"]
+        .map(String::from)
+        .to_vec()
+}
+
+/// TLS material for the shared HTTP client. All paths point to PEM files.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// Client certificate presented for mutual TLS. Requires `client_key`
+    /// and the `mtls` feature.
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    /// Additional CA certificates trusted when verifying servers.
+    pub ca_bundle: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A URL that receives a POST every time the machine enters a state.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Only notify when entering one of these states. Notifies on every
+    /// state when unset.
+    pub states: Option<Vec<String>>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
 pub struct AgentConfig {
-    pub actions: Vec<Action>,
+    pub actions: Vec<ActionConfig>,
     pub next_state: Option<String>,
+    /// Human-readable name recorded on the state's span and logs, for
+    /// configs whose state keys are opaque.
+    pub description: Option<String>,
+    /// Predicate, with placeholders resolved, deciding whether the actions
+    /// run. When it is false the state keeps its input buffer and goes
+    /// straight to `next_state`. Empty, `false`, `0`, `no` and `null` (in
+    /// any case) are false; anything else is true.
+    pub guard: Option<String>,
+    /// Labels such as `owner` or `critical`, recorded on the state's span
+    /// and in its entries of the run report.
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    /// Runs the state again when it fails. Without it a failed state moves
+    /// on, or routes to the dead-letter state, after one attempt.
+    pub retry: Option<RetryConfig>,
 }
 
+/// How a failed state is run again. A state fails when any of its actions
+/// fails; it is then re-run as a unit, every action on the same input
+/// buffer, until an attempt succeeds or `max_attempts` have been made. Only
+/// the last attempt's outputs are kept, except that a `run_once` action which
+/// succeeded in an earlier attempt isn't run again and keeps its output.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// Attempts in all, including the first.
+    pub max_attempts: u32,
+    /// Delays between attempts; the shared defaults when unset.
+    #[serde(default)]
+    pub backoff: Backoff,
+}
+
+/// An [`Action`] together with the settings every action kind accepts.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ActionConfig {
+    #[serde(flatten)]
+    pub action: Action,
+    /// Also stores the action's output under this name, where later states
+    /// can reference it with a `{"Named":"name"}` placeholder, or with
+    /// `{"Var":"name#/json/pointer"}` to reach into a JSON output.
+    pub output_name: Option<String>,
+    /// Labels recorded on the action's span, like a state's `tags`.
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    /// Skips the action whenever its state runs again after it succeeded,
+    /// for one-time setup like a registration call. The machine remembers
+    /// it by state key and position among the state's actions, across
+    /// config reloads.
+    #[serde(default)]
+    pub run_once: bool,
+    /// Actions in a state still run concurrently, but are started in
+    /// descending priority, in declaration order among equals, so one can
+    /// get ahead of the others, such as a cache warm-up.
+    #[serde(default)]
+    pub priority: i32,
+    /// Truncates the output entering the buffer, replacing the config's
+    /// `max_output`.
+    pub max_output: Option<OutputLimit>,
+}
+
+impl From<Action> for ActionConfig {
+    fn from(action: Action) -> Self {
+        Self {
+            action,
+            output_name: None,
+            tags: HashMap::new(),
+            run_once: false,
+            priority: 0,
+            max_output: None,
+        }
+    }
+}
+
+// actions live in configs built once per machine, so CallApi's many
+// settings aren't worth boxing
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Deserialize, Serialize, Clone, EnumDiscriminants)]
+#[strum_discriminants(derive(Hash))]
 #[serde(rename_all = "snake_case")]
 pub enum Action {
+    /// Sends an HTTP request and emits the response body. The kind of a
+    /// failed request's [`ApiError`](crate::http::ApiError) is stored as the
+    /// `last_api_error` variable.
+    ///
+    /// The status code of each response is also stored as the deprecated
+    /// `last_status` variable, which concurrent CallApi actions overwrite;
+    /// a `capture` of the status into a variable of its own replaces it.
+    #[serde(deserialize_with = "crate::models::deserialize_call_api")]
     CallApi(CallApiData),
     Llm(LlmData),
     SpawnAgent {
         #[serde(flatten)]
         agent_data: AgentData,
     },
-    WaitForInput,
-    Yield,
+    WaitForInput(Option<WaitForInputData>),
+    Yield(Option<YieldData>),
     GetAgentConfig(String),
     SetAgentConfig(String),
+    /// Validates the first buffer element against a JSON Schema, given either
+    /// inline or as a path to a schema file.
+    ValidateJsonSchema {
+        schema: String,
+    },
+    /// Evaluates a JMESPath expression against the first buffer element and
+    /// emits the result as JSON text.
+    Transform {
+        expr: String,
+    },
+    /// Runs one agent per input concurrently and aggregates their results.
+    MapAgent(MapAgentData),
+    /// Runs a sub-machine to completion and returns its joined result
+    /// buffer.
+    CallMachine(CallMachineData),
+    /// Sleeps, then produces `output` (with placeholders resolved) if set.
+    Delay {
+        duration_ms: u64,
+        output: Option<String>,
+    },
+    /// Dispatched to the [`CustomActionHandler`] registered as `handler`.
+    ///
+    /// [`CustomActionHandler`]: crate::action_handler::CustomActionHandler
+    Custom {
+        handler: String,
+        #[serde(default)]
+        params: serde_json::Value,
+    },
+    /// Stops the background agents this machine spawned with config label
+    /// `label` (placeholders resolved), along with any agents they spawned.
+    CancelAgent {
+        label: String,
+    },
+    /// Fails the action unless `actual` compares to `expected`, both with
+    /// placeholders resolved, so configs can check their own results.
+    Assert {
+        actual: String,
+        expected: String,
+        #[serde(default)]
+        match_type: MatchType,
+    },
+    /// Folds the response buffer into a single entry.
+    Merge {
+        strategy: MergeStrategy,
+    },
+    /// Does nothing and produces no output, for states that only
+    /// transition.
+    NoOp,
+    /// Runs `action`, failing if it hasn't finished after `timeout_ms`. A
+    /// timed out action is stopped, along with the agents and sub-machines
+    /// it started.
+    WithTimeout {
+        action: Box<Action>,
+        timeout_ms: u64,
+    },
+    /// Runs `actions` concurrently and produces the result of the first to
+    /// succeed, cancelling the others. Fails only if all of them fail.
+    Race {
+        actions: Vec<Action>,
+    },
+    /// Connects to a Server-Sent Events endpoint and forwards the data of
+    /// each event as a message, until the stream closes, an event's data is
+    /// `[DONE]` or the timeout passes. Produces no output.
+    Sse(SseData),
+    /// Ends the run as soon as it executes, cancelling the state's other
+    /// actions and skipping its `next_state`. `status` (placeholders
+    /// resolved) names the [`RunStatus`] to end with, `completed` by
+    /// default; `message`, if set, becomes the state's only output.
+    ///
+    /// [`RunStatus`]: crate::state_machine::RunStatus
+    Terminate {
+        status: Option<String>,
+        message: Option<String>,
+    },
+    /// Produces a JSON snapshot of the machine: a serialized
+    /// [`Introspection`].
+    ///
+    /// [`Introspection`]: crate::state_machine::Introspection
+    Introspect,
+}
+
+impl Action {
+    /// This action and every action nested in it, outermost first.
+    pub fn flatten(&self) -> Vec<&Action> {
+        let mut actions = vec![self];
+        let mut index = 0;
+        while let Some(action) = actions.get(index) {
+            match action {
+                Action::WithTimeout { action, .. } => actions.push(action),
+                Action::Race { actions: raced } => actions.extend(raced),
+                _ => {}
+            }
+            index += 1;
+        }
+        actions
+    }
+}
+
+/// Options controlling how a config file is turned into a [`Config`].
+#[derive(Debug, Default, Clone)]
+pub struct LoadOptions {
+    /// Expand `${VAR}` / `${VAR:-default}` tokens in the raw config text
+    /// before it is deserialized.
+    pub expand_env: bool,
+}
+
+/// Errors produced while loading a config, classified so they can be
+/// reported helpfully to someone editing the JSON by hand.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file could not be read.
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    /// The text is not well-formed JSON.
+    Syntax {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The JSON is well-formed but doesn't describe a valid config
+    /// (missing fields, unknown actions, wrong types).
+    Invalid {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// `${VAR}` tokens referenced unset variables without a default.
+    UndefinedEnvVars(Vec<String>),
+    /// `initial_state_key` doesn't name a state.
+    MissingInitialState(String),
+    /// A state's literal `next_state` (or the dead-letter state) doesn't
+    /// name a state.
+    DanglingTransition { state: String, next_state: String },
+    /// A placeholder delimiter is empty.
+    EmptyPlaceholderDelimiter,
+    /// Variables required by `env_check` are unset.
+    MissingEnvVars(Vec<String>),
+    /// The config was read from stdin, which had nothing on it.
+    EmptyStdin,
+    /// A placeholder in the templated field at `path`, such as
+    /// `states.fetch.actions[0].call_api.url`, doesn't parse.
+    MalformedPlaceholder { path: String, placeholder: String },
+    /// SpawnAgent actions in `states` name the same `output_label`, and
+    /// the config doesn't share it.
+    DuplicateOutputLabel { label: String, states: Vec<String> },
+    /// The CallApi at `path` uses a request template `templates` doesn't
+    /// define.
+    UnknownTemplate { path: String, name: String },
+    /// The CallApi at `path` isn't valid once its request template and
+    /// overrides are merged.
+    InvalidTemplatedCallApi { path: String, message: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => {
+                write!(f, "failed to read config {}: {}", path, source)
+            }
+            ConfigError::Syntax { message, .. } => write!(f, "invalid JSON: {}", message),
+            ConfigError::Invalid { message, .. } => write!(f, "invalid config: {}", message),
+            ConfigError::UndefinedEnvVars(names) => write!(
+                f,
+                "undefined environment variable(s) referenced in config: {} (use ${{VAR:-default}} to provide a fallback)",
+                names.join(", ")
+            ),
+            ConfigError::MissingInitialState(key) => {
+                write!(f, "initial state `{}` is not defined in `states`", key)
+            }
+            ConfigError::DanglingTransition { state, next_state } => write!(
+                f,
+                "state `{}` transitions to `{}`, which is not defined in `states`",
+                state, next_state
+            ),
+            ConfigError::EmptyPlaceholderDelimiter => {
+                write!(f, "placeholder delimiters must not be empty")
+            }
+            ConfigError::MissingEnvVars(names) => write!(
+                f,
+                "required environment variable(s) not set: {}",
+                names.join(", ")
+            ),
+            ConfigError::EmptyStdin => write!(f, "no config on stdin"),
+            ConfigError::MalformedPlaceholder { path, placeholder } => {
+                write!(f, "invalid placeholder {} in {}", placeholder, path)
+            }
+            ConfigError::DuplicateOutputLabel { label, states } => write!(
+                f,
+                "output label `{}` is used by {} agents (states {}); set duplicate_output_labels to \"share\" to share its stream",
+                label,
+                states.len(),
+                states.join(", ")
+            ),
+            ConfigError::UnknownTemplate { path, name } => write!(
+                f,
+                "request template `{}` used by {} is not defined in `templates`",
+                name, path
+            ),
+            ConfigError::InvalidTemplatedCallApi { path, message } => {
+                write!(f, "invalid config: {}: {}", path, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        let (line, column, message) = (e.line(), e.column(), e.to_string());
+        match e.classify() {
+            serde_json::error::Category::Data => ConfigError::Invalid {
+                line,
+                column,
+                message,
+            },
+            _ => ConfigError::Syntax {
+                line,
+                column,
+                message,
+            },
+        }
+    }
+}
+
+impl ConfigError {
+    /// Classifies a TOML error, locating it in the `data` that was parsed.
+    fn from_toml(e: toml::de::Error, data: &str) -> Self {
+        let (line, column) = match e.span() {
+            Some(span) => {
+                let before = &data[..span.start.min(data.len())];
+                let line_start = before.rfind('\n').map_or(0, |index| index + 1);
+                (
+                    before.matches('\n').count() + 1,
+                    before[line_start..].chars().count() + 1,
+                )
+            }
+            None => (0, 0),
+        };
+        ConfigError::Invalid {
+            line,
+            column,
+            message: e.message().to_string(),
+        }
+    }
+}
+
+/// The config path meaning "read the config from stdin", which must be
+/// JSON.
+pub const STDIN_PATH: &str = "-";
+
+/// The language a config file is written in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    #[default]
+    Json,
+    /// TOML has no null, so optional values are left out, and actions whose
+    /// data is optional, such as `wait_for_input`, take an empty table.
+    Toml,
+}
+
+impl ConfigFormat {
+    /// TOML for a `.toml` extension, JSON for anything else.
+    pub fn from_path(path: impl AsRef<std::path::Path>) -> Self {
+        match path.as_ref().extension() {
+            Some(extension) if extension.eq_ignore_ascii_case("toml") => ConfigFormat::Toml,
+            _ => ConfigFormat::Json,
+        }
+    }
+}
+
+/// Parses config JSON and checks that its transitions are consistent.
+///
+/// A top-level `templates` object defines named CallApi requests, which an
+/// action uses with `{"call_api": {"template": "name", "overrides": {...}}}`.
+/// They are expanded while parsing, so the parsed config only has complete
+/// CallApi actions. Objects are merged key by key, so an override of
+/// `signing.secret` keeps the template's other signing fields; any other
+/// value, `null` included, replaces the template's. Inline agent configs
+/// can use their parent's templates as well as their own.
+pub fn parse_config(data: &str) -> Result<Config, ConfigError> {
+    parse_config_as(data, ConfigFormat::Json)
+}
+
+/// Like [`parse_config`], for a config in `format`.
+pub fn parse_config_as(data: &str, format: ConfigFormat) -> Result<Config, ConfigError> {
+    let config = parse_config_unchecked_as(data, format)?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Parses config JSON without checking its transitions, for tools that
+/// report every issue through [`validate_config`](crate::validation::validate_config).
+pub fn parse_config_unchecked(data: &str) -> Result<Config, ConfigError> {
+    parse_config_unchecked_as(data, ConfigFormat::Json)
+}
+
+/// Like [`parse_config_unchecked`], for a config in `format`.
+pub fn parse_config_unchecked_as(data: &str, format: ConfigFormat) -> Result<Config, ConfigError> {
+    let mut config: Config = match format {
+        ConfigFormat::Json => serde_json::from_str(data)?,
+        ConfigFormat::Toml => toml::from_str(data).map_err(|e| ConfigError::from_toml(e, data))?,
+    };
+    config.expand_templates("", &HashMap::new())?;
+    Ok(config)
+}
+
+impl Config {
+    /// Replaces every CallApi that uses a request template with the
+    /// template's fields, overridden by its `overrides`. `inherited` are the
+    /// templates of the configs this one is inline in, and `path` its
+    /// location within them.
+    fn expand_templates(
+        &mut self,
+        path: &str,
+        inherited: &HashMap<String, serde_json::Value>,
+    ) -> Result<(), ConfigError> {
+        let mut templates = inherited.clone();
+        templates.extend(self.templates.clone());
+        let mut states: Vec<_> = self.states.iter_mut().collect();
+        states.sort_by_key(|(state_key, _)| *state_key);
+        for (state_key, state) in states {
+            for (index, action_config) in state.actions.iter_mut().enumerate() {
+                let path = format!("{}states.{}.actions[{}]", path, state_key, index);
+                expand_action_templates(&mut action_config.action, &path, &templates)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Expands the request templates used by `action`, at `path`, and by the
+/// actions and inline configs nested in it.
+fn expand_action_templates(
+    action: &mut Action,
+    path: &str,
+    templates: &HashMap<String, serde_json::Value>,
+) -> Result<(), ConfigError> {
+    let (key, config_source) = match action {
+        Action::CallApi(call_api_data) => {
+            let Some(reference) = call_api_data.template.take() else {
+                return Ok(());
+            };
+            let path = format!("{}.call_api", path);
+            let mut expanded = templates.get(&reference.template).cloned().ok_or_else(|| {
+                ConfigError::UnknownTemplate {
+                    path: path.clone(),
+                    name: reference.template.clone(),
+                }
+            })?;
+            merge_json(
+                &mut expanded,
+                serde_json::Value::Object(reference.overrides),
+            );
+            *call_api_data = serde_json::from_value(expanded).map_err(|e| {
+                ConfigError::InvalidTemplatedCallApi {
+                    path,
+                    message: e.to_string(),
+                }
+            })?;
+            return Ok(());
+        }
+        Action::WithTimeout { action, .. } => {
+            let path = format!("{}.with_timeout.action", path);
+            return expand_action_templates(action, &path, templates);
+        }
+        Action::Race { actions } => {
+            for (index, action) in actions.iter_mut().enumerate() {
+                let path = format!("{}.race.actions[{}]", path, index);
+                expand_action_templates(action, &path, templates)?;
+            }
+            return Ok(());
+        }
+        Action::SpawnAgent { agent_data } => ("spawn_agent", &mut agent_data.config_source),
+        Action::MapAgent(map_agent_data) => ("map_agent", &mut map_agent_data.config_source),
+        Action::CallMachine(call_machine_data) => {
+            ("call_machine", &mut call_machine_data.config_source)
+        }
+        _ => return Ok(()),
+    };
+    match config_source {
+        AgentConfigSource::Inline { agent_config } => {
+            let path = format!("{}.{}.agent_config.", path, key);
+            agent_config.expand_templates(&path, templates)
+        }
+        // parsed, with its own templates, when the agent starts
+        AgentConfigSource::File { .. } => Ok(()),
+    }
+}
+
+/// Merges `overrides` into `base`, recursing into objects both have.
+fn merge_json(base: &mut serde_json::Value, overrides: serde_json::Value) {
+    match (base, overrides) {
+        (serde_json::Value::Object(base), serde_json::Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+impl Config {
+    /// Checks the initial state and every literal transition name a state,
+    /// that every placeholder in a templated field parses, and that
+    /// SpawnAgent output labels are unique unless shared. Templated
+    /// `next_state` values are only known at runtime and skipped.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let delimiters = &self.placeholder_delimiters;
+        if delimiters.open.is_empty() || delimiters.close.is_empty() {
+            return Err(ConfigError::EmptyPlaceholderDelimiter);
+        }
+        if !self.states.contains_key(&self.initial_state_key) {
+            return Err(ConfigError::MissingInitialState(
+                self.initial_state_key.clone(),
+            ));
+        }
+
+        let mut state_keys: Vec<&String> = self.states.keys().collect();
+        state_keys.sort();
+        for state_key in state_keys {
+            let Some(next_state) = &self.states[state_key].next_state else {
+                continue;
+            };
+            if !delimiters.is_templated(next_state) && !self.states.contains_key(next_state) {
+                return Err(ConfigError::DanglingTransition {
+                    state: state_key.clone(),
+                    next_state: next_state.clone(),
+                });
+            }
+        }
+
+        if let Some(dead_letter) = &self.dead_letter_state {
+            if !self.states.contains_key(dead_letter) {
+                return Err(ConfigError::DanglingTransition {
+                    state: "dead_letter_state".to_string(),
+                    next_state: dead_letter.clone(),
+                });
+            }
+        }
+
+        let mut entry_points: Vec<(&String, &String)> = self.entry_points.iter().collect();
+        entry_points.sort();
+        for (entry, state_key) in entry_points {
+            if !self.states.contains_key(state_key) {
+                return Err(ConfigError::DanglingTransition {
+                    state: format!("entry_points.{}", entry),
+                    next_state: state_key.clone(),
+                });
+            }
+        }
+
+        if let Some((path, placeholder)) = crate::validation::first_malformed_placeholder(self) {
+            return Err(ConfigError::MalformedPlaceholder {
+                path,
+                placeholder: format!("{}{}{}", delimiters.open, placeholder, delimiters.close),
+            });
+        }
+
+        if self.duplicate_output_labels == DuplicateOutputLabels::Error {
+            if let Some((label, states)) = crate::validation::duplicate_output_labels(self)
+                .into_iter()
+                .next()
+            {
+                return Err(ConfigError::DuplicateOutputLabel {
+                    label: label.to_string(),
+                    states: states.into_iter().map(str::to_string).collect(),
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Expands `${VAR}` and `${VAR:-default}` tokens anywhere in raw config text.
+///
+/// Values are JSON-string escaped so they can be substituted inside string
+/// literals. Referencing an unset variable without a default is an error.
+pub fn expand_env_vars(raw: &str) -> Result<String, ConfigError> {
+    let re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)(?::-([^}]*))?\}").expect("valid regex");
+    let mut missing = Vec::new();
+
+    let expanded = re.replace_all(raw, |caps: &regex::Captures| {
+        let value = match (env::var(&caps[1]), caps.get(2)) {
+            (Ok(value), _) => value,
+            (Err(_), Some(default)) => default.as_str().to_string(),
+            (Err(_), None) => {
+                missing.push(caps[1].to_string());
+                return String::new();
+            }
+        };
+        let escaped = serde_json::to_string(&value).unwrap_or_default();
+        escaped[1..escaped.len() - 1].to_string()
+    });
+
+    if !missing.is_empty() {
+        return Err(ConfigError::UndefinedEnvVars(missing));
+    }
+
+    Ok(expanded.into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expand_env_vars() {
+        env::set_var("DSM_TEST_EXPAND_LABEL", "EnvAgent");
+        env::set_var("DSM_TEST_EXPAND_QUOTED", "say \"hi\"");
+
+        let raw = r#"{"label": "${DSM_TEST_EXPAND_LABEL}", "prompt": "${DSM_TEST_EXPAND_QUOTED}", "state": "${DSM_TEST_EXPAND_UNSET:-start}"}"#;
+        let expanded = expand_env_vars(raw).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&expanded).unwrap();
+
+        assert_eq!(value["label"], "EnvAgent");
+        assert_eq!(value["prompt"], "say \"hi\"");
+        assert_eq!(value["state"], "start");
+    }
+
+    #[test]
+    fn test_expand_env_vars_undefined() {
+        let err = expand_env_vars(r#"{"url": "${DSM_TEST_EXPAND_MISSING}"}"#).unwrap_err();
+        assert!(err.to_string().contains("DSM_TEST_EXPAND_MISSING"));
+    }
+
+    #[test]
+    fn test_parse_config_syntax_error() {
+        let err = parse_config("{\n  \"label\": \"broken\",\n  \"states\": {\n}").unwrap_err();
+        let ConfigError::Syntax { line, column, .. } = &err else {
+            panic!("expected a syntax error, got {:?}", err);
+        };
+        assert_eq!((*line, *column), (4, 1));
+        assert!(err.to_string().starts_with("invalid JSON"));
+    }
+
+    #[test]
+    fn test_parse_config_invalid_structure() {
+        let err = parse_config(r#"{"initial_state": "start", "states": {}}"#).unwrap_err();
+        assert!(
+            matches!(err, ConfigError::Invalid { line: 1, .. }),
+            "{:?}",
+            err
+        );
+        assert!(err.to_string().contains("missing field `label`"));
+    }
+
+    #[test]
+    fn test_parse_config_semantic_errors() {
+        let err = parse_config(
+            r#"{"initial_state": "begin", "label": "test", "states": {"start": {"actions": []}}}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(&err, ConfigError::MissingInitialState(key) if key == "begin"));
+        assert_eq!(
+            err.to_string(),
+            "initial state `begin` is not defined in `states`"
+        );
+
+        let err = parse_config(
+            r#"{"initial_state": "start", "label": "test", "states": {
+                "start": {"actions": [], "next_state": "finish"},
+                "routed": {"actions": [], "next_state": "{Output}"}
+            }}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(
+            &err,
+            ConfigError::DanglingTransition { state, next_state }
+                if state == "start" && next_state == "finish"
+        ));
+    }
+
+    #[test]
+    fn test_parse_config_rejects_malformed_placeholder() {
+        let err = parse_config(
+            r#"{"initial_state": "start", "label": "test", "states": {
+                "start": {"actions": [{"call_api": {
+                    "url": "https://api.example.com/{Input}/{\"Nmaed\":\"city\"}",
+                    "auth_header_name": "Authorization",
+                    "auth_header_value": "",
+                    "method": "GET"
+                }}]}
+            }}"#,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                ConfigError::MalformedPlaceholder { path, placeholder }
+                    if path == "states.start.actions[0].call_api.url"
+                        && placeholder == r#"{"Nmaed":"city"}"#
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            err.to_string(),
+            r#"invalid placeholder {"Nmaed":"city"} in states.start.actions[0].call_api.url"#
+        );
+    }
+
+    #[test]
+    fn test_parse_config_rejects_duplicate_output_labels() {
+        let config = |policy: &str| {
+            format!(
+                r#"{{"initial_state": "start", "label": "test", {} "states": {{
+                    "start": {{"actions": [
+                        {{"spawn_agent": {{"agent_config_file": "a.json", "input_label": "in",
+                            "output_label": "results", "is_background": true}}}},
+                        {{"spawn_agent": {{"agent_config_file": "b.json", "input_label": "in",
+                            "output_label": "results", "is_background": true}}}}
+                    ]}}
+                }}}}"#,
+                policy
+            )
+        };
+        let err = parse_config(&config("")).unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                ConfigError::DuplicateOutputLabel { label, states }
+                    if label == "results" && states == &["start", "start"]
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            err.to_string(),
+            r#"output label `results` is used by 2 agents (states start, start); set duplicate_output_labels to "share" to share its stream"#
+        );
+
+        let shared = parse_config(&config(r#""duplicate_output_labels": "share","#)).unwrap();
+        assert_eq!(shared.duplicate_output_labels, DuplicateOutputLabels::Share);
+    }
+
+    #[test]
+    fn test_parse_config_expands_request_templates() {
+        let config = parse_config(
+            r#"{"initial_state": "start", "label": "test",
+                "templates": {
+                    "github_api": {
+                        "url": "https://api.github.com/user",
+                        "auth_header_name": "Authorization",
+                        "auth_header_value": "Bearer {Input}",
+                        "method": "GET",
+                        "user_agent": "bot",
+                        "signing": {"secret": "s", "header": "X-Sig"}
+                    }
+                },
+                "states": {
+                    "start": {"actions": [
+                        {"call_api": {"template": "github_api"}},
+                        {"call_api": {"template": "github_api", "overrides": {
+                            "url": "https://api.github.com/repos",
+                            "method": "POST",
+                            "user_agent": null,
+                            "signing": {"secret": "other"}
+                        }}}
+                    ]}
+                }}"#,
+        )
+        .unwrap();
+        let calls: Vec<&CallApiData> = config.states["start"]
+            .actions
+            .iter()
+            .map(|action_config| match &action_config.action {
+                Action::CallApi(data) => data,
+                action => panic!("unexpected action {:?}", action),
+            })
+            .collect();
+
+        assert_eq!(calls[0].url, "https://api.github.com/user");
+        assert_eq!(calls[0].auth_header_value, "Bearer {Input}");
+        assert_eq!(calls[0].method, HttpMethod::GET);
+        assert_eq!(calls[0].user_agent.as_deref(), Some("bot"));
+
+        // overrides win, and the template fills in the rest
+        assert_eq!(calls[1].url, "https://api.github.com/repos");
+        assert_eq!(calls[1].method, HttpMethod::POST);
+        assert_eq!(calls[1].user_agent, None);
+        assert_eq!(calls[1].auth_header_name, "Authorization");
+        let signing = calls[1].signing.as_ref().unwrap();
+        assert_eq!(
+            (signing.secret.as_str(), signing.header.as_str()),
+            ("other", "X-Sig")
+        );
+    }
+
+    #[test]
+    fn test_parse_config_rejects_unknown_request_template() {
+        let config = |call_api: &str| {
+            format!(
+                r#"{{"initial_state": "start", "label": "test", "templates": {{}},
+                    "states": {{"start": {{"actions": [{{"call_api": {}}}]}}}}}}"#,
+                call_api
+            )
+        };
+        let err = parse_config(&config(r#"{"template": "missing"}"#)).unwrap_err();
+        assert!(matches!(&err, ConfigError::UnknownTemplate { name, .. } if name == "missing"));
+        assert_eq!(
+            err.to_string(),
+            "request template `missing` used by states.start.actions[0].call_api is not defined in `templates`"
+        );
+
+        let err = parse_config(&config(r#"{"template": "missing", "url": "/x"}"#)).unwrap_err();
+        let ConfigError::Invalid { line, message, .. } = &err else {
+            panic!("unexpected error {:?}", err);
+        };
+        assert!(
+            message.contains("unknown field `url` in a templated call_api"),
+            "{}",
+            message
+        );
+        assert_eq!(*line, 2);
+    }
+
+    #[test]
+    fn test_parse_config_expands_templates_only_in_call_api_actions() {
+        let config = parse_config(
+            r#"{"initial_state": "start", "label": "test",
+                "templates": {"api": {"auth_header_name": "Authorization"}},
+                "states": {"start": {"actions": [
+                    {"custom": {"handler": "h", "params": {"call_api": {"template": "api"}}}},
+                    {"race": {"actions": [{"call_api": {"template": "api",
+                        "overrides": {"url": "/raced", "auth_header_value": ""}}}]}},
+                    {"call_machine": {"agent_config": {
+                        "initial_state": "child", "label": "child",
+                        "states": {"child": {"actions": [{"call_api": {"template": "api"}}]}}
+                    }}}
+                ]}}}"#,
+        );
+        // the inline config's CallApi misses the fields the template lacks
+        let err = config.unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                ConfigError::InvalidTemplatedCallApi { path, message }
+                    if path == "states.start.actions[2].call_machine.agent_config.states.child.actions[0].call_api"
+                        && message.contains("missing field `url`")
+            ),
+            "{:?}",
+            err
+        );
+
+        let config = parse_config(
+            r#"{"initial_state": "start", "label": "test",
+                "templates": {"api": {"url": "/api", "auth_header_name": "Authorization",
+                                      "auth_header_value": ""}},
+                "states": {"start": {"actions": [
+                    {"custom": {"handler": "h", "params": {"call_api": {"template": "api"}}}},
+                    {"race": {"actions": [{"call_api": {"template": "api",
+                        "overrides": {"url": "/raced"}}}]}}
+                ]}}}"#,
+        )
+        .unwrap();
+        let actions = &config.states["start"].actions;
+        // custom params are the handler's, whatever they look like
+        let Action::Custom { params, .. } = &actions[0].action else {
+            panic!("unexpected action {:?}", actions[0].action);
+        };
+        assert_eq!(params["call_api"]["template"], "api");
+        let Action::Race { actions: raced } = &actions[1].action else {
+            panic!("unexpected action {:?}", actions[1].action);
+        };
+        let Action::CallApi(call_api_data) = &raced[0] else {
+            panic!("unexpected action {:?}", raced[0]);
+        };
+        assert_eq!(call_api_data.url, "/raced");
+        assert!(call_api_data.template.is_none());
+    }
+
+    #[test]
+    fn test_parse_config_placeholder_delimiters() {
+        let config = parse_config(
+            r#"{"initial_state": "start", "label": "test",
+                "placeholder_delimiters": {"open": "<<", "close": ">>"},
+                "states": {
+                    "start": {"actions": [], "next_state": "<<Output>>"},
+                    "braces": {"actions": [], "next_state": "{Output}"}
+                }}"#,
+        );
+        // `{Output}` is a literal state name under these delimiters
+        assert!(matches!(
+            &config,
+            Err(ConfigError::DanglingTransition { state, next_state })
+                if state == "braces" && next_state == "{Output}"
+        ));
+
+        let err = parse_config(
+            r#"{"initial_state": "start", "label": "test",
+                "placeholder_delimiters": {"open": "", "close": ">>"},
+                "states": {"start": {"actions": []}}}"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, ConfigError::EmptyPlaceholderDelimiter));
+    }
+
+    #[test]
+    fn test_parse_toml_config_errors() {
+        assert_eq!(
+            ConfigFormat::from_path("flows/weather.TOML"),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path("flows/weather.json"),
+            ConfigFormat::Json
+        );
+
+        let err = parse_config_as(
+            "label = \"broken\"\ninitial_state_key = \"start\"\nstates = 3\n",
+            ConfigFormat::Toml,
+        )
+        .unwrap_err();
+        match err {
+            ConfigError::Invalid { line, column, .. } => assert_eq!((line, column), (3, 10)),
+            other => panic!("unexpected error: {:?}", other),
+        }
+
+        let err = parse_config_as(
+            "label = \"broken\"\ninitial_state_key = \"start\"\n[states.start]\nactions = []\nnext_state = \"end\"\n",
+            ConfigFormat::Toml,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, ConfigError::DanglingTransition { .. }),
+            "{:?}",
+            err
+        );
+    }
 }
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::clock::Clock;
+use crate::config::DeadlockConfig;
+
+/// Tracks which actions of a machine tree are working and which are blocked
+/// on a WaitForInput, so a watchdog can tell when nothing is left to produce
+/// the inputs everyone is waiting for.
+///
+/// Actions that only wait on other machines (foreground SpawnAgent, MapAgent,
+/// CallMachine) count as neither. A wait on input from outside the tree
+/// counts as working, since that input may still arrive.
+#[derive(Debug, Default)]
+pub(crate) struct ActivityTracker {
+    activity: Mutex<Activity>,
+    watched: AtomicBool,
+    deadlocked: CancellationToken,
+    report: OnceLock<String>,
+}
+
+#[derive(Debug, Default)]
+struct Activity {
+    busy: usize,
+    waits: HashMap<u64, BlockedWait>,
+    next_wait_id: u64,
+    // bumped on every change, so a stall is only reported if nothing moved
+    generation: u64,
+}
+
+#[derive(Debug, Clone)]
+struct BlockedWait {
+    agent: String,
+    stream: String,
+}
+
+impl ActivityTracker {
+    /// Marks an action as working until the returned guard is dropped.
+    pub(crate) fn busy(&self) -> BusyGuard<'_> {
+        let mut activity = self.activity.lock().unwrap();
+        activity.busy += 1;
+        activity.generation += 1;
+        BusyGuard(self)
+    }
+
+    /// Marks a WaitForInput on input from outside the tree as working until
+    /// the returned guard is dropped.
+    pub(crate) fn external(&self) -> BusyGuard<'_> {
+        self.busy()
+    }
+
+    /// Marks `agent` as blocked on `stream` until the returned guard is
+    /// dropped.
+    pub(crate) fn waiting(&self, agent: &str, stream: &str) -> WaitGuard<'_> {
+        let mut activity = self.activity.lock().unwrap();
+        let id = activity.next_wait_id;
+        activity.next_wait_id += 1;
+        activity.generation += 1;
+        activity.waits.insert(
+            id,
+            BlockedWait {
+                agent: agent.to_string(),
+                stream: stream.to_string(),
+            },
+        );
+        WaitGuard(self, id)
+    }
+
+    /// Cancelled once an aborting watchdog has detected a deadlock.
+    pub(crate) fn deadlocked(&self) -> CancellationToken {
+        self.deadlocked.clone()
+    }
+
+    /// Describes the detected deadlock.
+    pub(crate) fn report(&self) -> String {
+        self.report
+            .get()
+            .cloned()
+            .unwrap_or_else(|| "deadlock detected".to_string())
+    }
+
+    /// Describes the current stall, with its generation, when every tracked
+    /// action is blocked on input.
+    fn stall(&self) -> Option<(u64, String)> {
+        let activity = self.activity.lock().unwrap();
+        if activity.busy > 0 || activity.waits.is_empty() {
+            return None;
+        }
+        let mut waits: Vec<String> = activity
+            .waits
+            .values()
+            .map(|wait| format!("{} on {}", wait.agent, wait.stream))
+            .collect();
+        waits.sort();
+        Some((
+            activity.generation,
+            format!(
+                "deadlock: every agent is blocked on WaitForInput ({})",
+                waits.join(", ")
+            ),
+        ))
+    }
+
+    /// Starts the watchdog for this tracker unless one is already running.
+    /// It stops when the returned handle is aborted or, when `abort` is set,
+    /// after it has reported a deadlock.
+    pub(crate) fn watch(
+        self: &Arc<Self>,
+        config: &DeadlockConfig,
+        clock: Arc<dyn Clock>,
+    ) -> Option<tokio::task::JoinHandle<()>> {
+        if self.watched.swap(true, Ordering::SeqCst) {
+            return None;
+        }
+        let tracker = self.clone();
+        let interval = Duration::from_millis(config.interval_ms);
+        let abort = config.abort;
+        Some(tokio::spawn(async move {
+            let poll = (interval / 4).max(Duration::from_millis(1));
+            let mut stalled_since: Option<(u64, Instant)> = None;
+            let mut reported_generation = None;
+            loop {
+                clock.sleep(poll).await;
+                let Some((generation, report)) = tracker.stall() else {
+                    stalled_since = None;
+                    continue;
+                };
+                let since = match stalled_since {
+                    Some((stalled, since)) if stalled == generation => since,
+                    _ => stalled_since.insert((generation, clock.now())).1,
+                };
+                if clock.now().duration_since(since) < interval
+                    || reported_generation == Some(generation)
+                {
+                    continue;
+                }
+                if abort {
+                    tracing::error!(%report, "aborting deadlocked machine");
+                    let _ = tracker.report.set(report);
+                    tracker.deadlocked.cancel();
+                    return;
+                }
+                tracing::warn!(%report, "machine appears deadlocked");
+                reported_generation = Some(generation);
+            }
+        }))
+    }
+}
+
+pub(crate) struct BusyGuard<'a>(&'a ActivityTracker);
+
+impl Drop for BusyGuard<'_> {
+    fn drop(&mut self) {
+        let mut activity = self.0.activity.lock().unwrap();
+        activity.busy -= 1;
+        activity.generation += 1;
+    }
+}
+
+pub(crate) struct WaitGuard<'a>(&'a ActivityTracker, u64);
+
+impl Drop for WaitGuard<'_> {
+    fn drop(&mut self) {
+        let mut activity = self.0.activity.lock().unwrap();
+        activity.waits.remove(&self.1);
+        activity.generation += 1;
+    }
+}
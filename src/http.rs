@@ -0,0 +1,772 @@
+use std::collections::BTreeMap;
+use std::io::Write as _;
+use std::time::Duration;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+use anyhow::Context as _;
+
+use crate::config::{HttpClientConfig, TlsConfig};
+use crate::models::{SignedPart, SigningAlgorithm, TokenSource};
+
+/// User-Agent sent when neither the config nor the action overrides it.
+pub const DEFAULT_USER_AGENT: &str =
+    concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// Stand-in logged in place of sensitive header values and credentials.
+pub const REDACTED: &str = "[REDACTED]";
+
+/// Renders `headers` for logging, masking the values of any header named in
+/// `sensitive` (case-insensitively).
+pub fn redact_headers<'a>(
+    headers: &reqwest::header::HeaderMap,
+    sensitive: impl IntoIterator<Item = &'a str> + Clone,
+) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let is_sensitive = sensitive
+                .clone()
+                .into_iter()
+                .any(|sensitive| name.as_str().eq_ignore_ascii_case(sensitive));
+            let value = if is_sensitive {
+                REDACTED.to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+/// Headers by lowercase name, with the values of a repeated header joined
+/// by `, `.
+pub fn join_headers(
+    headers: impl IntoIterator<Item = (String, String)>,
+) -> BTreeMap<String, String> {
+    let mut joined: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers {
+        match joined.get_mut(&name) {
+            Some(values) => {
+                values.push_str(", ");
+                values.push_str(&value);
+            }
+            None => {
+                joined.insert(name, value);
+            }
+        }
+    }
+    joined
+}
+
+/// Joins a relative `url` onto `base_url` with exactly one slash between
+/// them. Absolute URLs, and any URL when there is no base, are returned
+/// unchanged.
+pub fn resolve_url(base_url: Option<&str>, url: &str) -> String {
+    match base_url {
+        Some(base_url) if reqwest::Url::parse(url).is_err() => {
+            let path = url.trim_start_matches('/');
+            if path.is_empty() {
+                return base_url.to_string();
+            }
+            format!("{}/{}", base_url.trim_end_matches('/'), path)
+        }
+        _ => url.to_string(),
+    }
+}
+
+/// Builds the shared HTTP client used by every CallApi action of a machine.
+pub fn build_client(config: &HttpClientConfig) -> Result<reqwest::Client, anyhow::Error> {
+    Ok(client_builder(config)?.build()?)
+}
+
+/// A builder for the client [`build_client`] builds, for settings the config
+/// doesn't carry.
+pub(crate) fn client_builder(
+    config: &HttpClientConfig,
+) -> Result<reqwest::ClientBuilder, anyhow::Error> {
+    let user_agent = config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+    let mut builder = reqwest::Client::builder()
+        .user_agent(user_agent)
+        .gzip(config.gzip)
+        .brotli(config.brotli)
+        .deflate(config.deflate);
+    if config.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    }
+    if let Some(pool_idle_timeout_ms) = config.pool_idle_timeout_ms {
+        builder = builder.pool_idle_timeout(Duration::from_millis(pool_idle_timeout_ms));
+    }
+    if let Some(pool_max_idle_per_host) = config.pool_max_idle_per_host {
+        builder = builder.pool_max_idle_per_host(pool_max_idle_per_host);
+    }
+    if let Some(tcp_keepalive_ms) = config.tcp_keepalive_ms {
+        builder = builder.tcp_keepalive(Duration::from_millis(tcp_keepalive_ms));
+    }
+    if let Some(tls) = &config.tls {
+        builder = apply_tls(builder, tls)?;
+    }
+    if config.cookies {
+        builder = builder.cookie_store(true);
+    }
+    Ok(builder)
+}
+
+fn apply_tls(
+    mut builder: reqwest::ClientBuilder,
+    tls: &TlsConfig,
+) -> Result<reqwest::ClientBuilder, anyhow::Error> {
+    if let Some(ca_bundle) = &tls.ca_bundle {
+        let pem = std::fs::read(ca_bundle)
+            .with_context(|| format!("failed to read CA bundle from {}", ca_bundle))?;
+        for certificate in reqwest::Certificate::from_pem_bundle(&pem)
+            .with_context(|| format!("invalid CA bundle {}", ca_bundle))?
+        {
+            builder = builder.add_root_certificate(certificate);
+        }
+    }
+
+    match (&tls.client_cert, &tls.client_key) {
+        (None, None) => Ok(builder),
+        (Some(cert), Some(key)) => apply_identity(builder, cert, key),
+        _ => anyhow::bail!("tls.client_cert and tls.client_key must be set together"),
+    }
+}
+
+#[cfg(feature = "mtls")]
+fn apply_identity(
+    builder: reqwest::ClientBuilder,
+    cert: &str,
+    key: &str,
+) -> Result<reqwest::ClientBuilder, anyhow::Error> {
+    let mut pem = std::fs::read(cert)
+        .with_context(|| format!("failed to read client certificate from {}", cert))?;
+    pem.push(b'\n');
+    pem.extend(
+        std::fs::read(key).with_context(|| format!("failed to read client key from {}", key))?,
+    );
+    let identity = reqwest::Identity::from_pem(&pem)
+        .with_context(|| format!("invalid client certificate {} / key {}", cert, key))?;
+    Ok(builder.use_rustls_tls().identity(identity))
+}
+
+#[cfg(not(feature = "mtls"))]
+fn apply_identity(
+    _builder: reqwest::ClientBuilder,
+    _cert: &str,
+    _key: &str,
+) -> Result<reqwest::ClientBuilder, anyhow::Error> {
+    anyhow::bail!("client certificates require the `mtls` feature")
+}
+
+/// Reads an auth token from its source, trimming surrounding whitespace.
+pub async fn read_token(source: &TokenSource) -> Result<String, anyhow::Error> {
+    let token = match source {
+        TokenSource::File(path) => tokio::fs::read_to_string(path)
+            .await
+            .with_context(|| format!("failed to read auth token from {}", path))?,
+        TokenSource::Command(command) => {
+            let output = tokio::process::Command::new("sh")
+                .arg("-c")
+                .arg(command)
+                .output()
+                .await
+                .with_context(|| format!("failed to run auth token command {:?}", command))?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "auth token command {:?} exited with {}",
+                    command,
+                    output.status
+                );
+            }
+            String::from_utf8(output.stdout).context("auth token command output is not UTF-8")?
+        }
+    };
+    Ok(token.trim().to_string())
+}
+
+/// The target of the `rel="next"` entry of a `Link` header, as used by
+/// paginated APIs: `<https://api.example.com/items?page=2>; rel="next"`.
+pub fn next_link(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    headers
+        .get_all(reqwest::header::LINK)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .find_map(|link| {
+            let mut parts = link.split(';');
+            let target = parts.next()?.trim();
+            let is_next = parts.any(|param| {
+                let param = param.trim().to_ascii_lowercase();
+                param == "rel=\"next\"" || param == "rel=next"
+            });
+            let target = target.strip_prefix('<')?.strip_suffix('>')?;
+            is_next.then(|| target.to_string())
+        })
+}
+
+/// Whether `headers` declare a JSON body, as `application/json` or a
+/// `+json` type such as `application/problem+json`.
+pub fn is_json_content_type(headers: &reqwest::header::HeaderMap) -> bool {
+    let Some(content_type) = headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    mime == "application/json" || mime.ends_with("+json")
+}
+
+/// The hex-encoded signature of a request's `parts` under `secret`.
+pub fn sign_request(
+    algorithm: SigningAlgorithm,
+    secret: &[u8],
+    parts: &[SignedPart],
+    method: &str,
+    url: &reqwest::Url,
+    body: &[u8],
+) -> String {
+    let mut message = Vec::new();
+    for (index, part) in parts.iter().enumerate() {
+        if index > 0 {
+            message.push(b'\n');
+        }
+        match part {
+            SignedPart::Method => message.extend_from_slice(method.as_bytes()),
+            SignedPart::Path => {
+                message.extend_from_slice(url.path().as_bytes());
+                if let Some(query) = url.query() {
+                    message.push(b'?');
+                    message.extend_from_slice(query.as_bytes());
+                }
+            }
+            SignedPart::Body => message.extend_from_slice(body),
+        }
+    }
+    let algorithm = match algorithm {
+        SigningAlgorithm::HmacSha256 => ring::hmac::HMAC_SHA256,
+    };
+    let key = ring::hmac::Key::new(algorithm, secret);
+    hex(ring::hmac::sign(&key, &message).as_ref())
+}
+
+/// `bytes` as lowercase hex.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Splits a `text/event-stream` body into the data of its events, a chunk
+/// at a time. Comments and fields other than `data` are ignored, and an
+/// event still open when the body ends is dropped, as the SSE spec requires.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    // bytes after the last complete line
+    partial: Vec<u8>,
+    // `data` values of the event being read
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Feeds the next chunk of the body, returning the data of each event it
+    /// completes, with the values of several `data` lines joined by newlines.
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.partial.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        let mut start = 0;
+        while let Some(offset) = self.partial[start..]
+            .iter()
+            .position(|byte| matches!(byte, b'\n' | b'\r'))
+        {
+            let end = start + offset;
+            let ending = match self.partial.get(end..end + 2) {
+                Some(b"\r\n") => 2,
+                // a \r ending the chunk may be the start of a \r\n
+                None if self.partial[end] == b'\r' => break,
+                _ => 1,
+            };
+            let line = String::from_utf8_lossy(&self.partial[start..end]).into_owned();
+            self.line(&line, &mut events);
+            start = end + ending;
+        }
+        self.partial.drain(..start);
+        events
+    }
+
+    fn line(&mut self, line: &str, events: &mut Vec<String>) {
+        if line.is_empty() {
+            if !self.data.is_empty() {
+                events.push(self.data.join("\n"));
+                self.data.clear();
+            }
+            return;
+        }
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        // comments have an empty field name
+        if field == "data" {
+            self.data.push(value.to_string());
+        }
+    }
+}
+
+/// The file name a `Content-Disposition` header suggests, preferring an
+/// RFC 5987 `filename*` over `filename`. Only the last path component is
+/// kept, so a malicious name can't point outside the download directory.
+pub fn content_disposition_filename(headers: &reqwest::header::HeaderMap) -> Option<String> {
+    let value = headers
+        .get(reqwest::header::CONTENT_DISPOSITION)?
+        .to_str()
+        .ok()?;
+    let mut filename = None;
+    let mut extended = None;
+    for param in value.split(';').skip(1) {
+        let Some((name, value)) = param.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "filename" => filename = Some(value.trim_matches('"').to_string()),
+            "filename*" => {
+                // charset'language'percent-encoded-name
+                let encoded = value.splitn(3, '\'').nth(2)?;
+                extended = Some(
+                    percent_encoding::percent_decode_str(encoded)
+                        .decode_utf8_lossy()
+                        .into_owned(),
+                );
+            }
+            _ => {}
+        }
+    }
+    let name = extended.or(filename)?;
+    let name = std::path::Path::new(&name).file_name()?.to_str()?;
+    Some(name.to_string()).filter(|name| !name.is_empty() && name != "..")
+}
+
+/// Why an HTTP request failed, for telling a timeout from an unreachable
+/// host from an error response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiError {
+    /// The request or its response took longer than allowed.
+    Timeout,
+    /// No connection could be made: DNS failure, refused connection, TLS
+    /// handshake failure.
+    Connect,
+    /// The response body could not be read or decoded.
+    Decode,
+    /// The response had an error status, where one is treated as failure.
+    Status(u16),
+    Other,
+}
+
+impl ApiError {
+    pub fn from_reqwest(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            ApiError::Timeout
+        } else if e.is_connect() {
+            ApiError::Connect
+        } else if e.is_decode() {
+            ApiError::Decode
+        } else if let Some(status) = e.status() {
+            ApiError::Status(status.as_u16())
+        } else {
+            ApiError::Other
+        }
+    }
+
+    /// Classifies the first HTTP error in `e`'s chain, or `None` if the
+    /// failure wasn't an HTTP error.
+    pub fn classify(e: &anyhow::Error) -> Option<Self> {
+        e.chain()
+            .find_map(|cause| cause.downcast_ref::<reqwest::Error>())
+            .map(Self::from_reqwest)
+    }
+
+    /// `timeout`, `connect`, `decode`, `status` or `other`, as placed in
+    /// the `last_api_error` variable and dead-letter error context.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::Timeout => "timeout",
+            ApiError::Connect => "connect",
+            ApiError::Decode => "decode",
+            ApiError::Status(_) => "status",
+            ApiError::Other => "other",
+        }
+    }
+}
+
+/// Gzip-compresses a request body.
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>, anyhow::Error> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read as _;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_content_disposition_filename() {
+        let filename = |value: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::CONTENT_DISPOSITION, value.parse().unwrap());
+            content_disposition_filename(&headers)
+        };
+        assert_eq!(
+            filename(r#"attachment; filename="report.pdf""#).as_deref(),
+            Some("report.pdf")
+        );
+        assert_eq!(
+            filename("attachment; filename=plain.txt").as_deref(),
+            Some("plain.txt")
+        );
+        assert_eq!(
+            filename(
+                r#"attachment; filename="fallback.txt"; filename*=UTF-8''na%C3%AFve%20notes.txt"#
+            )
+            .as_deref(),
+            Some("naïve notes.txt")
+        );
+        assert_eq!(
+            filename(r#"attachment; filename="../../etc/passwd""#).as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(filename("inline"), None);
+        assert_eq!(filename(r#"attachment; filename="..""#), None);
+    }
+
+    #[test]
+    fn test_resolve_url_joins_relative_paths() {
+        let base = Some("https://api.example.com/v1");
+        assert_eq!(
+            resolve_url(base, "weather/tokyo"),
+            "https://api.example.com/v1/weather/tokyo"
+        );
+        assert_eq!(
+            resolve_url(base, "weather?city=oslo"),
+            "https://api.example.com/v1/weather?city=oslo"
+        );
+        assert_eq!(resolve_url(None, "weather/tokyo"), "weather/tokyo");
+    }
+
+    #[test]
+    fn test_resolve_url_absolute_overrides_base() {
+        let base = Some("https://api.example.com/v1");
+        assert_eq!(
+            resolve_url(base, "http://localhost:8080/weather"),
+            "http://localhost:8080/weather"
+        );
+    }
+
+    #[test]
+    fn test_resolve_url_normalizes_slashes() {
+        for base in ["https://api.example.com/v1", "https://api.example.com/v1/"] {
+            for path in ["weather", "/weather", "//weather"] {
+                assert_eq!(
+                    resolve_url(Some(base), path),
+                    "https://api.example.com/v1/weather",
+                    "{} + {}",
+                    base,
+                    path
+                );
+            }
+        }
+        assert_eq!(
+            resolve_url(Some("https://api.example.com/v1/"), ""),
+            "https://api.example.com/v1/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_client_tuning_options() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("multiplexed"))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = build_client(&HttpClientConfig {
+            http2_prior_knowledge: true,
+            pool_idle_timeout_ms: Some(30_000),
+            pool_max_idle_per_host: Some(4),
+            tcp_keepalive_ms: Some(15_000),
+            ..Default::default()
+        })
+        .unwrap();
+        let (first, second) = tokio::join!(
+            client.get(server.uri()).send(),
+            client.get(server.uri()).send()
+        );
+        for response in [first.unwrap(), second.unwrap()] {
+            assert_eq!(response.version(), reqwest::Version::HTTP_2);
+            assert_eq!(response.text().await.unwrap(), "multiplexed");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gzip_response_is_decoded() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("Content-Encoding", "gzip")
+                    .set_body_bytes(gzip(b"compressed weather report").unwrap()),
+            )
+            .mount(&server)
+            .await;
+
+        let client = build_client(&HttpClientConfig::default()).unwrap();
+        let body = client
+            .get(server.uri())
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "compressed weather report");
+    }
+
+    #[test]
+    fn test_next_link_finds_rel_next() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            reqwest::header::LINK,
+            r#"<https://api.example.com/items?page=1>; rel="prev", <https://api.example.com/items?page=3>; rel="next""#
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            next_link(&headers).as_deref(),
+            Some("https://api.example.com/items?page=3")
+        );
+
+        headers.insert(
+            reqwest::header::LINK,
+            r#"</items?page=1>; rel="first""#.parse().unwrap(),
+        );
+        assert_eq!(next_link(&headers), None);
+    }
+
+    #[test]
+    fn test_is_json_content_type() {
+        let headers = |content_type: &str| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            headers.insert(reqwest::header::CONTENT_TYPE, content_type.parse().unwrap());
+            headers
+        };
+        assert!(is_json_content_type(&headers("application/json")));
+        assert!(is_json_content_type(&headers(
+            "Application/JSON; charset=utf-8"
+        )));
+        assert!(is_json_content_type(&headers("application/problem+json")));
+        assert!(!is_json_content_type(&headers("text/plain")));
+        assert!(!is_json_content_type(&reqwest::header::HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_sign_request_known_answer() {
+        let url = reqwest::Url::parse("https://api.example.com/orders").unwrap();
+        let signature = sign_request(
+            SigningAlgorithm::HmacSha256,
+            b"key",
+            &[SignedPart::Body],
+            "POST",
+            &url,
+            b"The quick brown fox jumps over the lazy dog",
+        );
+        assert_eq!(
+            signature,
+            "f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8"
+        );
+    }
+
+    #[test]
+    fn test_sse_parser_splits_events() {
+        let mut parser = SseParser::default();
+        let body =
+            b": keepalive\nevent: delta\ndata: one\n\ndata:two\ndata: lines\r\n\r\nid: 3\n\n";
+        assert_eq!(parser.push(body), ["one", "two\nlines"]);
+        // unterminated events are dropped
+        assert!(parser.push(b"data: open").is_empty());
+    }
+
+    #[test]
+    fn test_sse_parser_joins_split_chunks() {
+        let mut parser = SseParser::default();
+        let mut events = Vec::new();
+        for chunk in [
+            &b"da"[..],
+            b"ta: caf\xc3",
+            b"\xa9\r",
+            b"\n\r",
+            b"\ndata: [DONE]\n\n",
+        ] {
+            events.extend(parser.push(chunk));
+        }
+        assert_eq!(events, ["café", "[DONE]"]);
+    }
+
+    #[test]
+    fn test_gzip_round_trip() {
+        let compressed = gzip(b"hello").unwrap();
+        let mut decoded = String::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "hello");
+    }
+
+    #[tokio::test]
+    async fn test_read_token_from_command() {
+        let token = read_token(&TokenSource::Command("echo '  secret '".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(token, "secret");
+    }
+
+    #[test]
+    fn test_client_cert_requires_key() {
+        let config = HttpClientConfig {
+            tls: Some(TlsConfig {
+                client_cert: Some("client.pem".to_string()),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let err = build_client(&config).unwrap_err();
+        assert!(err.to_string().contains("must be set together"));
+    }
+
+    /// Starts a TLS server on localhost that only completes the handshake for
+    /// clients presenting a certificate signed by the returned CA. Returns the
+    /// port and the PEM paths for the CA, client certificate, and client key.
+    #[cfg(feature = "mtls")]
+    async fn start_mtls_server() -> (u16, String, String, String) {
+        use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+        use std::sync::Arc;
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+        use tokio_rustls::rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+        use tokio_rustls::rustls::server::WebPkiClientVerifier;
+        use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca_cert = ca_params.self_signed(&ca_key).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca_cert, &ca_key)
+            .unwrap();
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["client".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca_cert, &ca_key)
+            .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca_cert.der().clone()).unwrap();
+        let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+            .build()
+            .unwrap();
+        let server_config = ServerConfig::builder()
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(
+                vec![server_cert.der().clone()],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(server_key.serialize_der())),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(server_config));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            loop {
+                let Ok((stream, _)) = listener.accept().await else {
+                    return;
+                };
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let mut request = [0u8; 4096];
+                    let _ = stream.read(&mut request).await;
+                    let _ = stream
+                        .write_all(
+                            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok",
+                        )
+                        .await;
+                    let _ = stream.shutdown().await;
+                });
+            }
+        });
+
+        let dir = std::env::temp_dir().join(format!("dsm_test_mtls_{}", port));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, pem: String| {
+            let path = dir.join(name);
+            std::fs::write(&path, pem).unwrap();
+            path.to_str().unwrap().to_string()
+        };
+        (
+            port,
+            write("ca.pem", ca_cert.pem()),
+            write("client.pem", client_cert.pem()),
+            write("client.key", client_key.serialize_pem()),
+        )
+    }
+
+    #[cfg(feature = "mtls")]
+    #[tokio::test]
+    async fn test_mtls_handshake() {
+        let (port, ca, cert, key) = start_mtls_server().await;
+        let url = format!("https://localhost:{}/", port);
+
+        let with_cert = build_client(&HttpClientConfig {
+            tls: Some(TlsConfig {
+                client_cert: Some(cert),
+                client_key: Some(key),
+                ca_bundle: Some(ca.clone()),
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        let body = with_cert
+            .get(&url)
+            .send()
+            .await
+            .unwrap()
+            .text()
+            .await
+            .unwrap();
+        assert_eq!(body, "ok");
+
+        let without_cert = build_client(&HttpClientConfig {
+            tls: Some(TlsConfig {
+                ca_bundle: Some(ca),
+                ..Default::default()
+            }),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(without_cert.get(&url).send().await.is_err());
+    }
+}
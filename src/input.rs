@@ -0,0 +1,146 @@
+//! Backpressure for a machine's input channel.
+//!
+//! Input normally arrives on a broadcast channel, so any number of
+//! subscribers see every message, but a producer outpacing WaitForInput
+//! fills the channel and the oldest unread messages are dropped (reported
+//! as `Lagged`). An [`InputSender`] from
+//! [`StateMachine::input_sender`](crate::state_machine::StateMachine::input_sender)
+//! instead makes producers wait while the channel holds as many unread
+//! inputs as it can, so none are lost.
+//!
+//! The tradeoff: credits are returned only by the machine's own reads, so a
+//! slow run slows every producer, and anything else subscribed to the same
+//! channel doesn't hold producers back and can still lag.
+
+use std::sync::Arc;
+
+use tokio::sync::{broadcast, Semaphore};
+
+/// Sends inputs to a machine, waiting while `capacity` of them are unread.
+#[derive(Debug, Clone)]
+pub struct InputSender {
+    tx: broadcast::Sender<String>,
+    // one permit per free slot in the channel; the machine adds one back
+    // for each input it reads
+    credits: Arc<Semaphore>,
+}
+
+impl InputSender {
+    /// A sender for a new channel of `capacity`, its receiver, and the
+    /// credits the receiving machine returns.
+    pub(crate) fn channel(capacity: usize) -> (Self, broadcast::Receiver<String>, Arc<Semaphore>) {
+        let (tx, rx) = broadcast::channel(capacity);
+        let credits = Arc::new(Semaphore::new(capacity));
+        let sender = Self {
+            tx,
+            credits: credits.clone(),
+        };
+        (sender, rx, credits)
+    }
+
+    /// Sends `input` once the machine has room for it.
+    pub async fn send(&self, input: impl Into<String>) -> Result<(), anyhow::Error> {
+        self.credits
+            .acquire()
+            .await
+            .map_err(|_| anyhow::anyhow!("input channel closed"))?
+            .forget();
+        self.tx
+            .send(input.into())
+            .map_err(|_| anyhow::anyhow!("machine stopped reading input"))?;
+        Ok(())
+    }
+
+    /// Sends `input` if the machine has room for it now, failing otherwise.
+    pub fn try_send(&self, input: impl Into<String>) -> Result<(), anyhow::Error> {
+        self.credits
+            .try_acquire()
+            .map_err(|_| anyhow::anyhow!("input channel full"))?
+            .forget();
+        self.tx
+            .send(input.into())
+            .map_err(|_| anyhow::anyhow!("machine stopped reading input"))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Action, AgentConfig, Config};
+    use crate::state_machine::StateMachine;
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    /// Waits for each input, then yields it after a pause.
+    fn slow_echo(inputs: u64) -> Config {
+        let state = |actions: Vec<Action>, next_state: &str| AgentConfig {
+            actions: actions.into_iter().map(Into::into).collect(),
+            next_state: Some(next_state.to_string()),
+            ..Default::default()
+        };
+        Config {
+            label: "slow".to_string(),
+            initial_state_key: "wait".to_string(),
+            states: HashMap::from([
+                (
+                    "wait".to_string(),
+                    state(vec![Action::WaitForInput(None)], "reply"),
+                ),
+                (
+                    "reply".to_string(),
+                    state(
+                        vec![
+                            Action::Yield(None),
+                            Action::Delay {
+                                duration_ms: 2,
+                                output: None,
+                            },
+                        ],
+                        "wait",
+                    ),
+                ),
+            ]),
+            max_iterations: Some(2 * inputs),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fast_producer_loses_no_inputs() {
+        const INPUTS: u64 = 40;
+        let (_, unused_rx) = broadcast::channel(1);
+        let (output_tx, mut output_rx) = broadcast::channel(INPUTS as usize);
+        let mut machine = StateMachine::new_with_config(slow_echo(INPUTS)).unwrap();
+        machine.connect(unused_rx, output_tx);
+        let input = machine.input_sender(4);
+
+        let producer = tokio::spawn(async move {
+            for index in 0..INPUTS {
+                input.send(index.to_string()).await.unwrap();
+            }
+        });
+        tokio::time::timeout(Duration::from_secs(10), machine.run())
+            .await
+            .unwrap()
+            .unwrap();
+        producer.await.unwrap();
+
+        let outputs: Vec<String> = std::iter::from_fn(|| output_rx.try_recv().ok()).collect();
+        let expected: Vec<String> = (0..INPUTS).map(|index| index.to_string()).collect();
+        assert_eq!(outputs, expected);
+    }
+
+    #[tokio::test]
+    async fn test_try_send_fails_when_full() {
+        let (sender, mut rx, credits) = InputSender::channel(2);
+        sender.try_send("a").unwrap();
+        sender.try_send("b").unwrap();
+        let err = sender.try_send("c").unwrap_err();
+        assert_eq!(err.to_string(), "input channel full");
+
+        assert_eq!(rx.recv().await.unwrap(), "a");
+        credits.add_permits(1);
+        sender.try_send("c").unwrap();
+    }
+}
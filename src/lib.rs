@@ -0,0 +1,24 @@
+pub mod action_handler;
+pub mod actor;
+pub mod backoff;
+pub mod batch;
+pub mod cache;
+pub mod checkpoint;
+pub mod clock;
+pub mod config;
+pub mod deadlock;
+pub mod http;
+pub mod input;
+pub mod llm;
+pub mod logging;
+pub mod models;
+pub mod observer;
+pub mod policy;
+pub mod rate_limit;
+pub mod replay;
+pub mod state_machine;
+pub mod step;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod test_utils;
+pub mod truncate;
+pub mod validation;
@@ -0,0 +1,96 @@
+use anyhow::Context as _;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+/// A fully resolved prompt handed to an [`LlmProvider`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct LlmRequest {
+    pub user_prompt: String,
+    pub system_prompt: Option<String>,
+    /// JSON Schema the response must conform to. Providers should request
+    /// structured output (e.g. `response_format`) when this is set.
+    pub response_schema: Option<serde_json::Value>,
+}
+
+/// Backend that completes prompts for the Llm action.
+#[async_trait]
+pub trait LlmProvider: Send + Sync {
+    async fn complete(&self, request: LlmRequest) -> Result<String, anyhow::Error>;
+}
+
+/// Connection settings for an OpenAI-compatible chat completions API.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LlmProviderConfig {
+    /// Base URL up to, but excluding, `/chat/completions`.
+    pub base_url: String,
+    pub api_key: Option<String>,
+    pub model: String,
+}
+
+impl std::fmt::Debug for LlmProviderConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LlmProviderConfig")
+            .field("base_url", &self.base_url)
+            .field(
+                "api_key",
+                &self.api_key.as_ref().map(|_| crate::http::REDACTED),
+            )
+            .field("model", &self.model)
+            .finish()
+    }
+}
+
+/// [`LlmProvider`] for any API speaking the OpenAI chat completions protocol.
+pub struct OpenAiCompatibleProvider {
+    client: reqwest::Client,
+    config: LlmProviderConfig,
+}
+
+impl OpenAiCompatibleProvider {
+    pub fn new(client: reqwest::Client, config: LlmProviderConfig) -> Self {
+        Self { client, config }
+    }
+}
+
+#[async_trait]
+impl LlmProvider for OpenAiCompatibleProvider {
+    async fn complete(&self, request: LlmRequest) -> Result<String, anyhow::Error> {
+        let mut messages = Vec::new();
+        if let Some(system_prompt) = &request.system_prompt {
+            messages.push(serde_json::json!({ "role": "system", "content": system_prompt }));
+        }
+        messages.push(serde_json::json!({ "role": "user", "content": request.user_prompt }));
+
+        let mut body = serde_json::json!({
+            "model": self.config.model,
+            "messages": messages,
+        });
+        if let Some(schema) = &request.response_schema {
+            body["response_format"] = serde_json::json!({
+                "type": "json_schema",
+                "json_schema": { "name": "response", "schema": schema, "strict": true },
+            });
+        }
+
+        let url = format!(
+            "{}/chat/completions",
+            self.config.base_url.trim_end_matches('/')
+        );
+        let mut http_request = self.client.post(&url).json(&body);
+        if let Some(api_key) = &self.config.api_key {
+            http_request = http_request.bearer_auth(api_key);
+        }
+        let response: serde_json::Value = http_request
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("LLM provider returned a non-JSON response")?;
+
+        response["choices"][0]["message"]["content"]
+            .as_str()
+            .map(str::to_string)
+            .context("LLM provider response has no message content")
+    }
+}
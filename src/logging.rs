@@ -0,0 +1,51 @@
+use tracing_subscriber::filter::{LevelFilter, ParseError};
+use tracing_subscriber::EnvFilter;
+
+/// Environment variable holding `EnvFilter` directives, e.g.
+/// `dynamic_state_machine=debug,reqwest=warn`.
+pub const LOG_ENV_VAR: &str = "RUST_LOG";
+
+/// Builds the log filter from `directives`, falling back to INFO for
+/// anything they don't cover (or everything, when unset).
+pub fn env_filter(directives: Option<&str>) -> Result<EnvFilter, ParseError> {
+    EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse(directives.unwrap_or_default())
+}
+
+/// Installs the global subscriber, configured from [`LOG_ENV_VAR`].
+pub fn init() -> Result<(), anyhow::Error> {
+    let directives = std::env::var(LOG_ENV_VAR).ok();
+    let filter = env_filter(directives.as_deref())?;
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .try_init()
+        .map_err(|e| anyhow::anyhow!(e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_filter_parses_directives() {
+        let filter = env_filter(Some("dynamic_state_machine=trace,reqwest=warn")).unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::TRACE));
+        let rendered = filter.to_string();
+        assert!(
+            rendered.contains("dynamic_state_machine=trace"),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("reqwest=warn"), "{}", rendered);
+
+        assert!(env_filter(Some("dynamic_state_machine=loud")).is_err());
+    }
+
+    #[test]
+    fn test_env_filter_defaults_to_info() {
+        let filter = env_filter(None).unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::INFO));
+        assert_eq!(filter.to_string(), "info");
+    }
+}
@@ -1,20 +1,123 @@
-mod config;
-mod models;
-mod state_machine;
+use anyhow::{Context as _, Result};
+use dynamic_state_machine::config::{parse_config_unchecked_as, ConfigFormat, STDIN_PATH};
+use dynamic_state_machine::logging;
+use dynamic_state_machine::state_machine::StateMachine;
+use dynamic_state_machine::step::{StepCommand, Stepper};
+use dynamic_state_machine::validation::{validate_config, Severity};
+use std::io::{Read as _, Write as _};
+use tokio::io::AsyncBufReadExt as _;
 
-use anyhow::Result;
-use state_machine::StateMachine;
+const DEFAULT_CONFIG_PATH: &str = "config.json";
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
+    logging::init()?;
 
-    let state_machine = StateMachine::new("config.json").await?;
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("validate") => {
+            let config_path = args
+                .next()
+                .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+            validate(&config_path)
+        }
+        Some("--step") => {
+            let config_path = args
+                .next()
+                .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+            step(&config_path).await
+        }
+        // stdin only for an explicit `-`, so a run under a pipe or a
+        // service manager still finds its default config
+        Some(config_path) => run(config_path).await,
+        None => run(DEFAULT_CONFIG_PATH).await,
+    }
+}
+
+/// Runs the config at `config_path`, or from stdin for `-`.
+async fn run(config_path: &str) -> Result<()> {
+    let state_machine = StateMachine::new(config_path).await?;
     state_machine.run().await?;
 
     tracing::info!("State machine execution completed.");
 
     Ok(())
 }
+
+/// Prints every issue in the config at `config_path`, exiting nonzero if any
+/// is an error.
+fn validate(config_path: &str) -> Result<()> {
+    let data = if config_path == STDIN_PATH {
+        let mut data = String::new();
+        std::io::stdin()
+            .read_to_string(&mut data)
+            .context("failed to read config from stdin")?;
+        data
+    } else {
+        std::fs::read_to_string(config_path)
+            .with_context(|| format!("failed to read config {}", config_path))?
+    };
+    let config = parse_config_unchecked_as(&data, ConfigFormat::from_path(config_path))?;
+
+    let issues = validate_config(&config);
+    for issue in &issues {
+        println!("{}", issue);
+    }
+    if issues.iter().any(|issue| issue.severity == Severity::Error) {
+        std::process::exit(1);
+    }
+    println!("{}: ok", config_path);
+    Ok(())
+}
+
+/// Runs the config at `config_path` one state at a time, reading a command
+/// from stdin before each state.
+async fn step(config_path: &str) -> Result<()> {
+    if config_path == STDIN_PATH {
+        anyhow::bail!("--step reads commands from stdin, so its config must come from a file");
+    }
+    let state_machine = StateMachine::new(config_path).await?;
+    let mut stepper = Stepper::new(state_machine, Vec::new())?;
+    let mut lines = tokio::io::BufReader::new(tokio::io::stdin()).lines();
+    println!("commands: next, buffer, goto <state>, vars, quit");
+    loop {
+        match stepper.next_state() {
+            Some(state_key) => print!("[{}] > ", state_key),
+            None => print!("[finished] > "),
+        }
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next_line().await? else {
+            return Ok(());
+        };
+        let command = match line.parse::<StepCommand>() {
+            Ok(command) => command,
+            Err(e) => {
+                println!("{}", e);
+                continue;
+            }
+        };
+        match command {
+            StepCommand::Next => {
+                if let Some(status) = stepper.step().await? {
+                    println!("finished: {:?}", status);
+                }
+            }
+            StepCommand::Buffer => {
+                for (index, entry) in stepper.buffer().iter().enumerate() {
+                    println!("{}: {}", index, entry);
+                }
+            }
+            StepCommand::Goto(state_key) => {
+                if let Err(e) = stepper.goto(&state_key) {
+                    println!("{}", e);
+                }
+            }
+            StepCommand::Vars => {
+                for (name, value) in stepper.vars() {
+                    println!("{} = {}", name, value);
+                }
+            }
+            StepCommand::Quit => return Ok(()),
+        }
+    }
+}
@@ -1,10 +1,13 @@
+use crate::backoff::Backoff;
 use crate::config::Config;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-// Start of Selection
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Default, Deserialize, Serialize, Clone, PartialEq, Eq)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum HttpMethod {
+    #[default]
     GET,
     POST,
     PUT,
@@ -28,45 +31,637 @@ impl From<&HttpMethod> for reqwest::Method {
     }
 }
 
-impl Default for HttpMethod {
-    fn default() -> Self {
-        HttpMethod::GET
-    }
-}
-
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Default, Deserialize, Serialize, Clone)]
 pub struct CallApiData {
+    /// Placeholders are resolved against the response buffer, e.g.
+    /// `"https://api.example.com/weather/{Input}"`.
     pub url: String,
     pub auth_header_name: String,
+    /// Placeholders are resolved against the response buffer, e.g.
+    /// `"Basic {base64(Input)}"`.
     pub auth_header_value: String,
+    /// Extra request headers; values have placeholders resolved, so they can
+    /// carry what an earlier request captured, e.g.
+    /// `"Bearer {\"Var\":\"token#/access_token\"}"`, or a response header
+    /// an earlier CallApi's `capture` stored.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// HEAD and OPTIONS requests emit the response's status and headers as
+    /// JSON, `{"status": 200, "headers": {...}}` with sensitive headers
+    /// masked, instead of its empty body, unless `capture` picks from those.
     #[serde(default)]
     pub method: HttpMethod,
     pub body: Option<String>,
+    /// Sends the contents of this file, whose path has placeholders resolved,
+    /// as the body instead of `body`, for large or binary payloads.
+    pub body_file: Option<String>,
+    /// Gzip the request body and send it with `Content-Encoding: gzip`.
+    #[serde(default)]
+    pub compress_body: bool,
+    /// Overrides the client's User-Agent for this call. Placeholders are
+    /// resolved against the response buffer.
+    pub user_agent: Option<String>,
+    /// Reads the credential from a file or command instead of using
+    /// `auth_header_value` alone; the token is appended to
+    /// `auth_header_value` (e.g. `"Bearer "`). On a 401 the token is re-read
+    /// and the request retried once.
+    pub auth_token_source: Option<TokenSource>,
+    /// Forwards the response body chunk by chunk to a stream instead of
+    /// returning it, for large or long-lived bodies such as SSE.
+    pub stream_response: Option<StreamResponseData>,
+    /// Follows next-page links or cursors, collecting every page's records
+    /// into one JSON array.
+    pub pagination: Option<PaginationData>,
+    /// Checks the response body before it enters the buffer.
+    #[serde(default)]
+    pub response_format: ResponseFormat,
+    /// Re-serializes a checked JSON body compactly.
+    #[serde(default)]
+    pub normalize_json: bool,
+    /// Signs the request, for APIs that authenticate payloads by HMAC.
+    pub signing: Option<SigningData>,
+    /// Always sends the request, bypassing the client's response cache.
+    #[serde(default)]
+    pub no_cache: bool,
+    /// Base URLs to spread requests across. Each request goes to one picked
+    /// by weight, with `url` resolved against it like the config's
+    /// `base_url`; on a connection error or 5xx response the request is
+    /// sent to another until none are left. Not used with `pagination`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<WeightedTarget>,
+    /// Fails the request if it hasn't completed after this long, with a
+    /// `timeout` [`ApiError`](crate::http::ApiError).
+    pub timeout_ms: Option<u64>,
+    /// Streams the response body to this file, with placeholders resolved,
+    /// and emits the file's path instead of the body. A path ending in `/`
+    /// names a directory, where the file is named by the response's
+    /// `Content-Disposition` filename or else the URL's last segment. The
+    /// body is written to a `.part` file beside it and renamed once
+    /// complete, so error statuses and failed downloads leave nothing at the
+    /// path. Saved responses bypass the response cache, and the action
+    /// fails while the machine records or replays responses. Not used with
+    /// `capture`.
+    pub save_to: Option<String>,
+    /// Parts of the response to keep instead of the body. Captures with an
+    /// `into` store their value in that variable; the rest make up the
+    /// action's output, joined by newlines. Not used with `stream_response`
+    /// or `save_to`.
+    #[serde(default)]
+    pub capture: Vec<Capture>,
+    /// Set while a config is parsed for a CallApi written as a use of a
+    /// request template, until
+    /// [`parse_config`](crate::config::parse_config) replaces the action
+    /// with the template's fields.
+    #[serde(skip)]
+    pub template: Option<TemplateReference>,
+}
+
+/// A CallApi written as `{"template": "name", "overrides": {...}}`, using
+/// one of the config's request `templates`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TemplateReference {
+    pub template: String,
+    /// CallApi fields replacing the template's; see
+    /// [`parse_config`](crate::config::parse_config).
+    #[serde(default)]
+    pub overrides: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Deserializes a CallApi's fields, or a [`TemplateReference`] that leaves
+/// them to be filled in from the template.
+pub(crate) fn deserialize_call_api<'de, D>(deserializer: D) -> Result<CallApiData, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error as _;
+
+    let value = serde_json::Value::deserialize(deserializer)?;
+    let Some(fields) = value
+        .as_object()
+        .filter(|fields| fields.contains_key("template"))
+    else {
+        return CallApiData::deserialize(value).map_err(D::Error::custom);
+    };
+    if let Some(field) = fields
+        .keys()
+        .find(|field| !["template", "overrides"].contains(&field.as_str()))
+    {
+        return Err(D::Error::custom(format!(
+            "unknown field `{}` in a templated call_api; put it in `overrides`",
+            field
+        )));
+    }
+    Ok(CallApiData {
+        template: Some(TemplateReference::deserialize(value).map_err(D::Error::custom)?),
+        ..Default::default()
+    })
+}
+
+/// A part of a CallApi response copied into a variable or the buffer, e.g.
+/// `{"from": "header", "name": "x-request-id", "into": "request_id"}`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Capture {
+    #[serde(flatten)]
+    pub source: CaptureSource,
+    /// Variable to store the value in, as an action's `output_name` would;
+    /// without one the value goes to the buffer.
+    pub into: Option<String>,
+}
+
+/// Where a [`Capture`] reads its value. A missing header or path fails the
+/// action.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "from", rename_all = "snake_case")]
+pub enum CaptureSource {
+    /// The checked body.
+    Body,
+    /// A value in the JSON body at an RFC 6901 pointer such as `/data/id`.
+    /// Strings are captured without quotes, other values as JSON.
+    Path { pointer: String },
+    /// A header, by case-insensitive name, with the values of a repeated
+    /// header joined by `, `.
+    Header { name: String },
+    /// The status code.
+    Status,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct WeightedTarget {
+    pub url: String,
+    /// Share of requests sent here first. Targets weighted 0 are only
+    /// failed over to.
+    #[serde(default = "default_target_weight")]
+    pub weight: u32,
+}
+
+fn default_target_weight() -> u32 {
+    1
+}
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct SigningData {
+    #[serde(default)]
+    pub algorithm: SigningAlgorithm,
+    /// Placeholders are resolved against the response buffer, e.g.
+    /// `{"Env":"WEBHOOK_SECRET"}`. A secret resolving to an empty string
+    /// fails the request instead of signing it.
+    pub secret: String,
+    /// Header the hex-encoded signature is sent in.
+    #[serde(default = "default_signature_header")]
+    pub header: String,
+    /// Request parts signed, joined by newlines, in this order.
+    #[serde(default = "default_signed_parts")]
+    pub parts: Vec<SignedPart>,
+}
+
+// Hand-written so the secret never reaches logs.
+impl std::fmt::Debug for SigningData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SigningData")
+            .field("algorithm", &self.algorithm)
+            .field("secret", &crate::http::REDACTED)
+            .field("header", &self.header)
+            .field("parts", &self.parts)
+            .finish()
+    }
+}
+
+fn default_signature_header() -> String {
+    "X-Signature".to_string()
+}
+
+fn default_signed_parts() -> Vec<SignedPart> {
+    vec![SignedPart::Method, SignedPart::Path, SignedPart::Body]
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SigningAlgorithm {
+    #[default]
+    HmacSha256,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SignedPart {
+    /// The upper-case method, e.g. `POST`.
+    Method,
+    /// The URL path with its query string, e.g. `/orders?page=2`.
+    Path,
+    /// The body bytes as sent, after any compression.
+    Body,
+}
+
+/// Whether a CallApi response body must be JSON. A body that isn't fails the
+/// action rather than reaching JSON-path placeholders downstream.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseFormat {
+    /// Any body, passed through as is.
+    #[default]
+    Text,
+    /// JSON when the response's Content-Type says so.
+    Auto,
+    Json,
+}
+
+// Hand-written so the credential never reaches logs.
+impl std::fmt::Debug for CallApiData {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallApiData")
+            .field("url", &self.url)
+            .field("auth_header_name", &self.auth_header_name)
+            .field("auth_header_value", &crate::http::REDACTED)
+            .field(
+                "headers",
+                &self
+                    .headers
+                    .keys()
+                    .map(|name| (name, crate::http::REDACTED))
+                    .collect::<std::collections::BTreeMap<_, _>>(),
+            )
+            .field("method", &self.method)
+            .field("body", &self.body)
+            .field("body_file", &self.body_file)
+            .field("compress_body", &self.compress_body)
+            .field("user_agent", &self.user_agent)
+            .field("auth_token_source", &self.auth_token_source)
+            .field("stream_response", &self.stream_response)
+            .field("pagination", &self.pagination)
+            .field("response_format", &self.response_format)
+            .field("normalize_json", &self.normalize_json)
+            .field("signing", &self.signing)
+            .field("no_cache", &self.no_cache)
+            .field("targets", &self.targets)
+            .field("timeout_ms", &self.timeout_ms)
+            .field("save_to", &self.save_to)
+            .field("capture", &self.capture)
+            .field("template", &self.template)
+            .finish()
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct SseData {
+    /// Endpoint serving `text/event-stream`, with placeholders resolved.
+    pub url: String,
+    /// Request headers; values have placeholders resolved.
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Named stream to forward events to instead of the machine's output
+    /// channel.
+    pub stream: Option<String>,
+    /// Stops reading after this long; the stream is read until it closes or
+    /// sends `[DONE]` when unset.
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct StreamResponseData {
+    /// Named stream to forward to instead of the machine's output channel.
+    pub stream: Option<String>,
+    /// Stops reading after this long; the body is read to its end when unset.
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PaginationData {
+    /// JMESPath expression giving the next page from each JSON page; a null
+    /// result ends pagination. Without it the `Link` header's `rel="next"`
+    /// target is followed.
+    pub next: Option<String>,
+    /// Query parameter the `next` value is sent in, on the action's URL, for
+    /// cursor-based APIs. Without it `next` is a URL, relative to the page.
+    pub cursor_param: Option<String>,
+    /// JMESPath expression selecting each page's records; arrays are
+    /// flattened into the result. Defaults to the whole page.
+    pub items: Option<String>,
+    /// Stops after this many pages even if more remain.
+    #[serde(default = "default_max_pages")]
+    pub max_pages: u32,
+}
+
+fn default_max_pages() -> u32 {
+    10
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenSource {
+    /// Path to a file containing the token.
+    File(String),
+    /// Shell command whose standard output is the token.
+    Command(String),
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct LlmData {
     pub user_prompt: String,
     pub system_prompt: Option<String>,
+    /// JSON Schema for a structured response. When set the model's reply
+    /// must be JSON conforming to it and is emitted as compact JSON.
+    pub response_schema: Option<serde_json::Value>,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct AgentData {
     #[serde(flatten)]
     pub config_source: AgentConfigSource,
     pub input_label: String,
     pub output_label: String,
+    /// Run the agent without waiting for it; the action produces no output.
+    /// It is stopped, even mid-action, when the parent shuts down.
     pub is_background: bool,
+    /// Binds additional child stream names to parent stream names.
+    #[serde(default)]
+    pub stream_bindings: HashMap<String, String>,
+    /// Aborts the agent, and any agents it spawned, after this long. A
+    /// foreground spawn then fails with a timeout error; a background agent
+    /// is terminated quietly.
+    pub timeout_ms: Option<u64>,
+    /// Restarts the agent after it exits. The timeout covers every attempt.
+    pub restart: Option<RestartPolicy>,
+    /// Delays between restarts; the shared defaults when unset.
+    pub restart_backoff: Option<Backoff>,
+    /// Variables `Env` placeholders in the agent resolve to before the
+    /// process environment, on top of any the parent machine has.
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Parts of the parent's session the agent starts with.
+    #[serde(default)]
+    pub inherit: AgentInheritance,
+}
+
+/// What a spawned agent takes over from its parent, so that it can act in
+/// a session the parent established. Nothing is inherited by default.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct AgentInheritance {
+    /// Sends the agent's requests with the parent's HTTP client, sharing its
+    /// cookies (with `http.cookies`), connections, TLS settings and
+    /// User-Agent instead of building one from the agent's config.
+    pub http_client: bool,
+    /// Starts the agent with a copy of the parent's variables, such as
+    /// stored outputs and captures, as they were when it was spawned.
+    /// Restarts start from the same copy.
+    pub variables: bool,
+}
+
+/// When a spawned agent is started again after its run ends.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    /// Restarts after an error or a dead-lettered run, at most `max` times.
+    OnFailure { max: u32 },
+    /// Restarts whenever the agent exits. A foreground spawn waits for the
+    /// agent's output, so for it this only restarts after errors.
+    Always,
+}
+
+impl RestartPolicy {
+    /// Whether an agent that has been restarted `restarts` times, and whose
+    /// last run `failed` with an error or a dead letter, should run again.
+    pub fn should_restart(&self, restarts: u32, failed: bool, is_background: bool) -> bool {
+        match self {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure { max } => failed && restarts < *max,
+            RestartPolicy::Always => failed || is_background,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(untagged)]
 pub enum AgentConfigSource {
     File { agent_config_file: String },
-    Inline { agent_config: Config },
+    Inline { agent_config: Box<Config> },
+}
+
+impl Default for AgentConfigSource {
+    /// An empty inline config.
+    fn default() -> Self {
+        AgentConfigSource::Inline {
+            agent_config: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CallMachineData {
+    #[serde(flatten)]
+    pub config_source: AgentConfigSource,
+    /// Seeds the sub-machine's buffer. Placeholders are resolved against the
+    /// response buffer.
+    pub input: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MapAgentData {
+    #[serde(flatten)]
+    pub config_source: AgentConfigSource,
+    /// One agent is spawned per input, seeded with it as its first buffer
+    /// element. Placeholders are resolved against the response buffer.
+    pub inputs: Vec<String>,
+    #[serde(default)]
+    pub aggregation: Aggregation,
+}
+
+/// How the results of a [`MapAgentData`] fan-out are combined, in input order.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    /// Newline-joined results.
+    #[default]
+    Concat,
+    First,
+    Last,
+    /// A JSON array; results that are valid JSON are embedded as values,
+    /// anything else as strings.
+    JsonArray,
+}
+
+impl Aggregation {
+    pub fn aggregate(&self, results: Vec<String>) -> String {
+        match self {
+            Aggregation::Concat => results.join("\n"),
+            Aggregation::First => results.into_iter().next().unwrap_or_default(),
+            Aggregation::Last => results.into_iter().last().unwrap_or_default(),
+            Aggregation::JsonArray => serde_json::Value::Array(
+                results
+                    .into_iter()
+                    .map(|result| {
+                        serde_json::from_str(&result).unwrap_or(serde_json::Value::String(result))
+                    })
+                    .collect(),
+            )
+            .to_string(),
+        }
+    }
+}
+
+/// How a Merge action folds the response buffer into one entry.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeStrategy {
+    Concat {
+        #[serde(default = "default_separator")]
+        sep: String,
+    },
+    /// Merges JSON objects key by key, recursing into nested objects; later
+    /// entries win. Values of different types under one key are an error.
+    JsonDeepMerge,
+    /// A JSON array; entries that are valid JSON are embedded as values,
+    /// anything else as strings.
+    JsonArray,
+}
+
+fn default_separator() -> String {
+    "\n".to_string()
+}
+
+impl MergeStrategy {
+    pub fn merge(&self, entries: &[String]) -> Result<String, anyhow::Error> {
+        match self {
+            MergeStrategy::Concat { sep } => Ok(entries.join(sep)),
+            MergeStrategy::JsonDeepMerge => {
+                let mut merged = serde_json::Value::Object(Default::default());
+                for (index, entry) in entries.iter().enumerate() {
+                    let value: serde_json::Value = serde_json::from_str(entry)
+                        .map_err(|e| anyhow::anyhow!("entry {} is not valid JSON: {}", index, e))?;
+                    if !value.is_object() {
+                        anyhow::bail!("entry {} is not a JSON object", index);
+                    }
+                    deep_merge(&mut merged, value, "$")?;
+                }
+                Ok(merged.to_string())
+            }
+            MergeStrategy::JsonArray => Ok(Aggregation::JsonArray.aggregate(entries.to_vec())),
+        }
+    }
+}
+
+fn deep_merge(
+    target: &mut serde_json::Value,
+    source: serde_json::Value,
+    path: &str,
+) -> Result<(), anyhow::Error> {
+    use serde_json::Value;
+
+    match (target, source) {
+        (Value::Object(target), Value::Object(source)) => {
+            for (key, value) in source {
+                let path = format!("{}.{}", path, key);
+                match target.get_mut(&key) {
+                    Some(existing) => deep_merge(existing, value, &path)?,
+                    None => {
+                        target.insert(key, value);
+                    }
+                }
+            }
+        }
+        (target, source) if json_type(target) == json_type(&source) => *target = source,
+        (target, source) => anyhow::bail!(
+            "cannot merge {} into {} at {}",
+            json_type(&source),
+            json_type(target),
+            path
+        ),
+    }
+    Ok(())
+}
+
+fn json_type(value: &serde_json::Value) -> &'static str {
+    match value {
+        serde_json::Value::Null => "null",
+        serde_json::Value::Bool(_) => "boolean",
+        serde_json::Value::Number(_) => "number",
+        serde_json::Value::String(_) => "string",
+        serde_json::Value::Array(_) => "array",
+        serde_json::Value::Object(_) => "object",
+    }
+}
+
+/// How an Assert action compares its `actual` value with `expected`.
+#[derive(Debug, Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchType {
+    #[default]
+    Equals,
+    /// `expected` is a regex that must match somewhere in `actual`.
+    Regex,
+    Contains,
+}
+
+impl MatchType {
+    pub fn matches(&self, actual: &str, expected: &str) -> Result<bool, regex::Error> {
+        Ok(match self {
+            MatchType::Equals => actual == expected,
+            MatchType::Regex => regex::Regex::new(expected)?.is_match(actual),
+            MatchType::Contains => actual.contains(expected),
+        })
+    }
+}
+
+impl std::fmt::Display for MatchType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MatchType::Equals => write!(f, "equal"),
+            MatchType::Regex => write!(f, "match"),
+            MatchType::Contains => write!(f, "contain"),
+        }
+    }
 }
 
 #[derive(Debug, Default, Clone, Deserialize, Serialize)]
 pub struct WaitForInputData {
+    #[serde(default)]
     pub data: String,
+    /// Named stream to read from instead of the machine's input channel.
+    pub stream: Option<String>,
+    /// Named streams to read from instead, highest priority first. When
+    /// several have messages ready the first is consumed, but a stream
+    /// passed over four times in a row is served next so it can't starve.
+    #[serde(default)]
+    pub streams: Vec<String>,
+    /// Regex an input must match to be accepted; others are skipped.
+    pub filter: Option<String>,
+    /// JMESPath expression evaluated against JSON inputs; the result replaces
+    /// the input. Inputs that aren't JSON or yield null are skipped.
+    pub extract: Option<String>,
+    /// Named stream on which any message cancels the wait, failing the
+    /// action instead of letting it run to its timeout.
+    pub cancel_stream: Option<String>,
+    /// Treat inputs as JSON `{"value": ..., "metadata": {...}}`. The action
+    /// outputs `value`, which `filter` and `extract` apply to, and stores
+    /// `metadata` with the source `stream` and receipt `timestamp` (ms since
+    /// the epoch) added as a named output under this name, for placeholders
+    /// like `{"Var":"meta#/stream"}`. Other inputs are taken as the value.
+    pub metadata_name: Option<String>,
+    /// How many times to keep receiving after falling behind a stream and
+    /// missing messages, taking the oldest still available; 3 if unset.
+    pub lag_retries: Option<u32>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct YieldData {
+    /// Named stream to send to instead of the machine's output channel.
+    pub stream: Option<String>,
+    /// Send each element of a JSON array as its own message, strings
+    /// without their quotes. Other responses are sent whole.
+    #[serde(default)]
+    pub split: bool,
+    /// Hold messages and send them in batches, each as one JSON array of
+    /// messages, instead of one at a time. Anything still held is sent when
+    /// the run finishes.
+    pub batch: Option<YieldBatch>,
+}
+
+/// When a batching Yield sends what it holds: once `max_messages` are
+/// held, or `max_delay_ms` after the first of them, whichever comes first.
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+pub struct YieldBatch {
+    pub max_messages: Option<usize>,
+    pub max_delay_ms: Option<u64>,
 }
@@ -0,0 +1,26 @@
+use crate::config::Action;
+
+/// Synchronous callbacks for machine lifecycle events.
+///
+/// Observers are invoked inline from the run loop, so implementations should
+/// return quickly. Every method defaults to a no-op.
+pub trait StateMachineObserver: Send + Sync {
+    /// Called before a state's actions are started.
+    fn on_state_enter(&self, _state_key: &str) {}
+
+    /// Called as each action finishes, in completion order.
+    fn on_action_complete(
+        &self,
+        _state_key: &str,
+        _action: &Action,
+        _result: &Result<Option<String>, anyhow::Error>,
+    ) {
+    }
+
+    /// Called when a spawned agent is started again under its restart
+    /// policy; `restarts` counts the restarts so far, including this one.
+    fn on_agent_restart(&self, _label: &str, _restarts: u32) {}
+
+    /// Called once with the final buffer when the machine returns.
+    fn on_finish(&self, _buffer: &[String]) {}
+}
@@ -0,0 +1,606 @@
+//! Limits on what a config may do, for embedding the engine with configs
+//! that aren't trusted, such as ones written by users or generated by an
+//! LLM.
+//!
+//! An [`ActionPolicy`] set with
+//! [`StateMachine::with_policy`](crate::state_machine::StateMachine::with_policy)
+//! is checked against every state's actions before a run starts, and again
+//! as each action executes, so configs loaded later (reloads, spawned agents)
+//! are held to it too. CallApi and Sse URLs are checked once their
+//! placeholders are resolved, and again at every redirect; the webhook and
+//! LLM provider URLs are checked with the config. Files and commands are
+//! checked as they're used.
+
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use crate::config::{Action, ActionDiscriminants, Config};
+use crate::models::TokenSource;
+
+/// Redirects followed per request, as by reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
+/// Which action kinds, HTTP hosts, local files and commands a machine may
+/// use. The default allows everything.
+#[derive(Debug, Clone, Default)]
+pub struct ActionPolicy {
+    // `None` allows every action kind
+    allowed_actions: Option<HashSet<ActionDiscriminants>>,
+    // lowercased; `None` allows every host
+    allowed_hosts: Option<HashSet<String>>,
+    // `None` allows every path
+    allowed_paths: Option<Vec<PathBuf>>,
+    commands_denied: bool,
+}
+
+impl ActionPolicy {
+    /// Allows only `actions`, on top of any allowed before. Wrappers like
+    /// WithTimeout and Race must be allowed along with the actions they run.
+    pub fn with_allowed_actions(
+        mut self,
+        actions: impl IntoIterator<Item = ActionDiscriminants>,
+    ) -> Self {
+        self.allowed_actions
+            .get_or_insert_with(HashSet::new)
+            .extend(actions);
+        self
+    }
+
+    /// Allows CallApi and Sse requests only to `hosts`, on top of any allowed
+    /// before. Hosts match exactly, ignoring case, so `api.example.com`
+    /// doesn't allow `example.com` or `eu.api.example.com`.
+    pub fn with_allowed_hosts<S: AsRef<str>>(mut self, hosts: impl IntoIterator<Item = S>) -> Self {
+        self.allowed_hosts
+            .get_or_insert_with(HashSet::new)
+            .extend(hosts.into_iter().map(|host| host.as_ref().to_lowercase()));
+        self
+    }
+
+    /// Allows CallApi to read and write local files (token files,
+    /// `body_file` and `save_to`) only within `dirs`, on top of any allowed
+    /// before. Paths are compared once `..` and symlinks are resolved.
+    pub fn with_allowed_paths<P: AsRef<Path>>(mut self, dirs: impl IntoIterator<Item = P>) -> Self {
+        self.allowed_paths
+            .get_or_insert_with(Vec::new)
+            .extend(dirs.into_iter().map(|dir| dir.as_ref().to_path_buf()));
+        self
+    }
+
+    /// Whether `command` auth token sources, which run through `sh -c`, may
+    /// run. The default allows them.
+    pub fn with_commands_allowed(mut self, allowed: bool) -> Self {
+        self.commands_denied = !allowed;
+        self
+    }
+
+    /// Fails if `action`, or any action nested in it, isn't allowed.
+    pub fn check_action(&self, action: &Action) -> Result<(), PolicyViolation> {
+        for action in action.flatten() {
+            let kind = ActionDiscriminants::from(action);
+            if let Some(allowed) = &self.allowed_actions {
+                if !allowed.contains(&kind) {
+                    return Err(PolicyViolation::Action { kind, state: None });
+                }
+            }
+            if let Action::CallApi(call_api_data) = action {
+                if let Some(TokenSource::Command(command)) = &call_api_data.auth_token_source {
+                    self.check_command(command)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails if `command` may not be run.
+    pub fn check_command(&self, command: &str) -> Result<(), PolicyViolation> {
+        if self.commands_denied {
+            return Err(PolicyViolation::Command(command.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Fails if the local file at `path` may not be read or written.
+    pub fn check_path(&self, path: &Path) -> Result<(), PolicyViolation> {
+        let Some(allowed) = &self.allowed_paths else {
+            return Ok(());
+        };
+        let within = normalize_path(path).is_some_and(|path| {
+            allowed
+                .iter()
+                .filter_map(|dir| normalize_path(dir))
+                .any(|dir| path.starts_with(dir))
+        });
+        if !within {
+            return Err(PolicyViolation::Path(path.display().to_string()));
+        }
+        Ok(())
+    }
+
+    /// Fails if a CallApi or Sse request may not be sent to `url`.
+    pub fn check_url(&self, url: &str) -> Result<(), PolicyViolation> {
+        let Some(allowed) = &self.allowed_hosts else {
+            return Ok(());
+        };
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase));
+        match host {
+            Some(host) if allowed.contains(&host) => Ok(()),
+            _ => Err(PolicyViolation::Host(url.to_string())),
+        }
+    }
+
+    /// Fails if `config`'s webhook or LLM provider URL isn't allowed, or on
+    /// the first action that isn't, naming its state. States are checked in
+    /// key order, so the error is stable.
+    pub fn check_config(&self, config: &Config) -> Result<(), PolicyViolation> {
+        if let Some(webhook) = &config.webhook {
+            self.check_url(&webhook.url)?;
+        }
+        if let Some(llm) = &config.llm {
+            self.check_url(&llm.base_url)?;
+        }
+        let mut states: Vec<_> = config.states.iter().collect();
+        states.sort_by_key(|(state_key, _)| *state_key);
+        for (state_key, state) in states {
+            for action_config in &state.actions {
+                self.check_action(&action_config.action)
+                    .map_err(|violation| violation.in_state(state_key))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An action or request an [`ActionPolicy`] doesn't allow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyViolation {
+    /// An action of a kind the policy doesn't allow, in `state` when found
+    /// by checking a config.
+    Action {
+        kind: ActionDiscriminants,
+        state: Option<String>,
+    },
+    /// A request to a URL whose host isn't allowed.
+    Host(String),
+    /// A local file outside the allowed paths.
+    Path(String),
+    /// A command, when commands aren't allowed.
+    Command(String),
+}
+
+impl PolicyViolation {
+    fn in_state(self, state_key: &str) -> Self {
+        match self {
+            PolicyViolation::Action { kind, .. } => PolicyViolation::Action {
+                kind,
+                state: Some(state_key.to_string()),
+            },
+            violation => violation,
+        }
+    }
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PolicyViolation::Action {
+                kind,
+                state: Some(state),
+            } => write!(
+                f,
+                "policy violation: {:?} actions are not allowed (state {})",
+                kind, state
+            ),
+            PolicyViolation::Action { kind, state: None } => {
+                write!(f, "policy violation: {:?} actions are not allowed", kind)
+            }
+            PolicyViolation::Host(url) => {
+                write!(f, "policy violation: requests to {} are not allowed", url)
+            }
+            PolicyViolation::Path(path) => {
+                write!(f, "policy violation: access to {} is not allowed", path)
+            }
+            PolicyViolation::Command(command) => {
+                write!(f, "policy violation: running {:?} is not allowed", command)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+/// A redirect policy for the HTTP client, following up to
+/// [`MAX_REDIRECTS`] redirects to URLs the current `policy` allows.
+pub(crate) fn redirect_policy(policy: Arc<RwLock<ActionPolicy>>) -> reqwest::redirect::Policy {
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        let checked = policy.read().unwrap().check_url(attempt.url().as_str());
+        match checked {
+            Ok(()) => attempt.follow(),
+            Err(violation) => attempt.error(violation),
+        }
+    })
+}
+
+/// `path` made absolute with symlinks and `..` resolved, as far as it
+/// exists. `None` if a part that doesn't exist yet climbs out with `..`,
+/// since a symlink could make that go anywhere.
+fn normalize_path(path: &Path) -> Option<PathBuf> {
+    let absolute = std::path::absolute(path).ok()?;
+    let components: Vec<Component> = absolute.components().collect();
+    (0..=components.len()).rev().find_map(|existing| {
+        let prefix: PathBuf = components[..existing].iter().collect();
+        let mut normal = prefix.canonicalize().ok()?;
+        for component in &components[existing..] {
+            match component {
+                Component::Normal(name) => normal.push(name),
+                Component::CurDir => {}
+                _ => return Some(None),
+            }
+        }
+        Some(Some(normal))
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_handler::CustomActionHandler;
+    use crate::config::AgentConfig;
+    use crate::models::CallApiData;
+    use crate::state_machine::StateMachine;
+    use crate::test_utils::MockApi;
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    /// Stands in for a handler with side effects, like running a command.
+    struct Exec;
+
+    #[async_trait]
+    impl CustomActionHandler for Exec {
+        async fn handle(
+            &self,
+            _params: &serde_json::Value,
+            _response_buffer: &[String],
+        ) -> Result<Option<String>, anyhow::Error> {
+            Ok(Some("executed".to_string()))
+        }
+    }
+
+    fn single_state(action: Action) -> Config {
+        Config {
+            label: "policy".to_string(),
+            initial_state_key: "run".to_string(),
+            states: HashMap::from([(
+                "run".to_string(),
+                AgentConfig {
+                    actions: vec![action.into()],
+                    next_state: None,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        }
+    }
+
+    fn exec_machine(policy: ActionPolicy) -> StateMachine {
+        let config = single_state(Action::Custom {
+            handler: "exec".to_string(),
+            params: serde_json::json!({ "command": "rm -rf /" }),
+        });
+        StateMachine::new_with_config(config)
+            .unwrap()
+            .with_action_handler("exec", Arc::new(Exec))
+            .with_policy(policy)
+    }
+
+    #[tokio::test]
+    async fn test_policy_rejects_omitted_action_before_running() {
+        let policy = ActionPolicy::default()
+            .with_allowed_actions([ActionDiscriminants::CallApi, ActionDiscriminants::NoOp]);
+        let err = exec_machine(policy).run().await.unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PolicyViolation>(),
+            Some(&PolicyViolation::Action {
+                kind: ActionDiscriminants::Custom,
+                state: Some("run".to_string()),
+            })
+        );
+        assert_eq!(
+            err.to_string(),
+            "policy violation: Custom actions are not allowed (state run)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_policy_allows_included_action() {
+        let policy = ActionPolicy::default().with_allowed_actions([ActionDiscriminants::Custom]);
+        assert_eq!(exec_machine(policy).run().await.unwrap(), ["executed"]);
+        // the default policy allows everything
+        let output = exec_machine(ActionPolicy::default()).run().await.unwrap();
+        assert_eq!(output, ["executed"]);
+    }
+
+    #[test]
+    fn test_policy_checks_nested_actions() {
+        let policy = ActionPolicy::default().with_allowed_actions([ActionDiscriminants::Race]);
+        let race = Action::Race {
+            actions: vec![Action::NoOp],
+        };
+        assert_eq!(
+            policy.check_action(&race),
+            Err(PolicyViolation::Action {
+                kind: ActionDiscriminants::NoOp,
+                state: None,
+            })
+        );
+        let policy = policy.with_allowed_actions([ActionDiscriminants::NoOp]);
+        assert_eq!(policy.check_action(&race), Ok(()));
+    }
+
+    #[test]
+    fn test_policy_matches_hosts_exactly() {
+        let policy = ActionPolicy::default().with_allowed_hosts(["API.example.com"]);
+        assert!(policy.check_url("https://api.example.com/orders").is_ok());
+        assert!(policy.check_url("https://example.com/").is_err());
+        assert!(policy.check_url("https://eu.api.example.com/").is_err());
+        assert!(policy.check_url("not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_policy_rejects_requests_to_other_hosts() {
+        let api = MockApi::start().await;
+        api.respond("GET", "/data", 200, "ok").await;
+        let config = single_state(Action::CallApi(CallApiData {
+            url: api.url("/data"),
+            auth_header_name: "Authorization".to_string(),
+            ..Default::default()
+        }));
+        // the failed state passes on the error context
+        let mut dead_lettered = Config {
+            dead_letter_state: Some("failed".to_string()),
+            ..config.clone()
+        };
+        dead_lettered.states.insert(
+            "failed".to_string(),
+            single_state(Action::Transform {
+                expr: "@".to_string(),
+            })
+            .states
+            .remove("run")
+            .unwrap(),
+        );
+
+        let allowed = ActionPolicy::default().with_allowed_hosts(["127.0.0.1"]);
+        let output = StateMachine::new_with_config(config)
+            .unwrap()
+            .with_policy(allowed)
+            .run()
+            .await
+            .unwrap();
+        assert_eq!(output, ["ok"]);
+
+        let other = ActionPolicy::default().with_allowed_hosts(["api.example.com"]);
+        let output = StateMachine::new_with_config(dead_lettered)
+            .unwrap()
+            .with_policy(other)
+            .run()
+            .await
+            .unwrap();
+        let error: serde_json::Value = serde_json::from_str(&output[0]).unwrap();
+        assert_eq!(
+            error["error"],
+            format!(
+                "policy violation: requests to {} are not allowed",
+                api.url("/data")
+            )
+        );
+        assert_eq!(api.requests().await.len(), 1);
+    }
+
+    fn call_api(url: String) -> CallApiData {
+        CallApiData {
+            url,
+            auth_header_name: "Authorization".to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Runs `call_api_data` under `policy`, returning the error chain.
+    async fn call_api_error(policy: ActionPolicy, call_api_data: CallApiData) -> String {
+        let err = StateMachine::new_with_config(single_state(Action::NoOp))
+            .unwrap()
+            .with_policy(policy)
+            .execute_action(&Action::CallApi(call_api_data), &[])
+            .await
+            .unwrap_err();
+        format!("{:#}", err)
+    }
+
+    /// A directory the policy allows and a file next to it that it doesn't.
+    fn confined_paths(name: &str) -> (PathBuf, PathBuf, ActionPolicy) {
+        let root = std::env::temp_dir().join(name);
+        let allowed = root.join("allowed");
+        std::fs::create_dir_all(&allowed).unwrap();
+        let outside = root.join("secret.txt");
+        std::fs::write(&outside, "secret").unwrap();
+        let policy = ActionPolicy::default().with_allowed_paths([&allowed]);
+        (allowed, outside, policy)
+    }
+
+    #[tokio::test]
+    async fn test_policy_rejects_token_commands() {
+        use crate::models::TokenSource;
+
+        let config = single_state(Action::CallApi(CallApiData {
+            auth_token_source: Some(TokenSource::Command("echo token".to_string())),
+            ..call_api("http://127.0.0.1:9/".to_string())
+        }));
+        let err = StateMachine::new_with_config(config)
+            .unwrap()
+            .with_policy(ActionPolicy::default().with_commands_allowed(false))
+            .run()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PolicyViolation>(),
+            Some(&PolicyViolation::Command("echo token".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_policy_confines_token_files() {
+        use crate::models::TokenSource;
+
+        let (allowed, outside, policy) = confined_paths("dsm_policy_token_file");
+        let api = MockApi::start().await;
+        api.respond("GET", "/data", 200, "ok").await;
+        // `..` can't climb out of an allowed directory
+        let escaping = allowed.join("../secret.txt");
+        let err = call_api_error(
+            policy.clone(),
+            CallApiData {
+                auth_token_source: Some(TokenSource::File(escaping.display().to_string())),
+                ..call_api(api.url("/data"))
+            },
+        )
+        .await;
+        assert!(err.contains("policy violation: access to"), "{}", err);
+        assert!(api.requests().await.is_empty());
+
+        std::fs::write(allowed.join("token"), "token").unwrap();
+        let output = StateMachine::new_with_config(single_state(Action::NoOp))
+            .unwrap()
+            .with_policy(policy)
+            .execute_action(
+                &Action::CallApi(CallApiData {
+                    auth_token_source: Some(TokenSource::File(
+                        allowed.join("token").display().to_string(),
+                    )),
+                    ..call_api(api.url("/data"))
+                }),
+                &[],
+            )
+            .await
+            .unwrap();
+        assert_eq!(output.as_deref(), Some("ok"));
+        std::fs::remove_dir_all(outside.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_policy_confines_body_files() {
+        let (_, outside, policy) = confined_paths("dsm_policy_body_file");
+        let err = call_api_error(
+            policy,
+            CallApiData {
+                body_file: Some(outside.display().to_string()),
+                ..call_api("http://127.0.0.1:9/".to_string())
+            },
+        )
+        .await;
+        assert!(err.contains("policy violation: access to"), "{}", err);
+        std::fs::remove_dir_all(outside.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_policy_confines_saved_responses() {
+        let (allowed, outside, policy) = confined_paths("dsm_policy_save_to");
+        let api = MockApi::start().await;
+        api.respond("GET", "/report", 200, "overwritten").await;
+        let err = call_api_error(
+            policy,
+            CallApiData {
+                save_to: Some(outside.display().to_string()),
+                ..call_api(api.url("/report"))
+            },
+        )
+        .await;
+        assert!(err.contains("policy violation: access to"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&outside).unwrap(), "secret");
+        assert!(std::fs::read_dir(&allowed).unwrap().next().is_none());
+        std::fs::remove_dir_all(outside.parent().unwrap()).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_policy_checks_every_redirect() {
+        use wiremock::ResponseTemplate;
+
+        let api = MockApi::start().await;
+        // the same server under a host the policy doesn't allow
+        let elsewhere = api.url("/end").replace("127.0.0.1", "localhost");
+        api.mount(
+            "GET",
+            "/start",
+            ResponseTemplate::new(302).insert_header("Location", elsewhere.as_str()),
+        )
+        .await;
+        api.respond("GET", "/end", 200, "leaked").await;
+        let policy = ActionPolicy::default().with_allowed_hosts(["127.0.0.1"]);
+        let err = call_api_error(policy, call_api(api.url("/start"))).await;
+        assert!(
+            err.contains(&format!(
+                "policy violation: requests to {} are not allowed",
+                elsewhere
+            )),
+            "{}",
+            err
+        );
+        assert!(api.requests_to("GET", "/end").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_policy_checks_webhook_url() {
+        use crate::config::WebhookConfig;
+
+        let config = Config {
+            webhook: Some(WebhookConfig {
+                url: "https://hooks.example.com/transitions".to_string(),
+                states: None,
+            }),
+            ..single_state(Action::NoOp)
+        };
+        let err = StateMachine::new_with_config(config)
+            .unwrap()
+            .with_policy(ActionPolicy::default().with_allowed_hosts(["api.example.com"]))
+            .run()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PolicyViolation>(),
+            Some(&PolicyViolation::Host(
+                "https://hooks.example.com/transitions".to_string()
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_policy_checks_llm_base_url() {
+        use crate::llm::LlmProviderConfig;
+
+        let config = Config {
+            llm: Some(LlmProviderConfig {
+                base_url: "https://llm.example.com/v1".to_string(),
+                api_key: None,
+                model: "model".to_string(),
+            }),
+            ..single_state(Action::NoOp)
+        };
+        let err = StateMachine::new_with_config(config)
+            .unwrap()
+            .with_policy(ActionPolicy::default().with_allowed_hosts(["api.example.com"]))
+            .run()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PolicyViolation>(),
+            Some(&PolicyViolation::Host(
+                "https://llm.example.com/v1".to_string()
+            ))
+        );
+    }
+}
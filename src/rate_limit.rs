@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::clock::{Clock, TokioClock};
+use crate::config::RateLimitConfig;
+
+/// Host key whose limit applies to hosts without one of their own.
+pub const ANY_HOST: &str = "*";
+
+/// Token-bucket rate limiter with one bucket per host.
+///
+/// Each bucket holds up to `burst` tokens and refills at
+/// `requests_per_second`. A request that finds the bucket empty reserves the
+/// next token and waits for it, so concurrent callers are paced in turn.
+#[derive(Debug)]
+pub struct RateLimiter {
+    limits: HashMap<String, RateLimitConfig>,
+    buckets: Mutex<HashMap<String, Bucket>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(HashMap::new())
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    // negative while requests are waiting on reserved tokens
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(limits: HashMap<String, RateLimitConfig>) -> Self {
+        Self {
+            limits,
+            buckets: Mutex::default(),
+            clock: Arc::new(TokioClock),
+        }
+    }
+
+    /// Refills buckets and waits for tokens on `clock`.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Waits until a request to `host` is allowed. Hosts without a limit
+    /// (and no [`ANY_HOST`] limit) are never delayed.
+    pub async fn acquire(&self, host: &str) {
+        let delay = self.reserve(host);
+        if !delay.is_zero() {
+            tracing::debug!(%host, ?delay, "rate limited");
+            self.clock.sleep(delay).await;
+        }
+    }
+
+    fn reserve(&self, host: &str) -> Duration {
+        let Some(limit) = self.limits.get(host).or_else(|| self.limits.get(ANY_HOST)) else {
+            return Duration::ZERO;
+        };
+        let rate = limit.requests_per_second;
+        if rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        let burst = f64::from(limit.burst.max(1));
+
+        let now: Instant = self.clock.now();
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(host.to_string()).or_insert(Bucket {
+            tokens: burst,
+            refilled_at: now,
+        });
+        let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+        bucket.refilled_at = now;
+
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / rate)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(host: &str, requests_per_second: f64, burst: u32) -> RateLimiter {
+        RateLimiter::new(HashMap::from([(
+            host.to_string(),
+            RateLimitConfig {
+                requests_per_second,
+                burst,
+            },
+        )]))
+    }
+
+    #[test]
+    fn test_reserve_paces_after_burst() {
+        let limiter = limiter("api.example.com", 10.0, 2);
+        assert_eq!(limiter.reserve("api.example.com"), Duration::ZERO);
+        assert_eq!(limiter.reserve("api.example.com"), Duration::ZERO);
+
+        let third = limiter.reserve("api.example.com");
+        let fourth = limiter.reserve("api.example.com");
+        assert!(third > Duration::from_millis(90) && third <= Duration::from_millis(100));
+        assert!(fourth > Duration::from_millis(190) && fourth <= Duration::from_millis(200));
+
+        // other hosts have their own (here: no) limit
+        assert_eq!(limiter.reserve("other.example.com"), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_any_host_limit_applies_per_host() {
+        let limiter = limiter(ANY_HOST, 1.0, 1);
+        assert_eq!(limiter.reserve("a.example.com"), Duration::ZERO);
+        assert_eq!(limiter.reserve("b.example.com"), Duration::ZERO);
+        assert!(limiter.reserve("a.example.com") > Duration::ZERO);
+    }
+}
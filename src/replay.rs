@@ -0,0 +1,98 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecorderMode {
+    /// Perform external calls and capture their responses.
+    Record,
+    /// Serve captured responses instead of performing external calls.
+    Replay,
+}
+
+/// Captures the responses of external actions (CallApi and Llm) keyed by a
+/// fingerprint of the resolved request, or serves them back, so a run can be
+/// reproduced without the network.
+///
+/// Identical requests are answered in the order they were recorded. Headers
+/// are not part of the fingerprint, so rotated credentials still match, and
+/// streamed CallApi responses are neither recorded nor replayed. A CallApi
+/// body is fingerprinted by a digest of the bytes sent, so a `body_file`
+/// edited since recording no longer matches.
+#[derive(Debug)]
+pub struct Recorder {
+    mode: RecorderMode,
+    path: PathBuf,
+    interactions: Mutex<BTreeMap<String, VecDeque<String>>>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RecordingFile {
+    interactions: BTreeMap<String, VecDeque<String>>,
+}
+
+impl Recorder {
+    /// Starts an empty recording, written to `path` by [`Recorder::save`].
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            mode: RecorderMode::Record,
+            path: path.into(),
+            interactions: Mutex::default(),
+        }
+    }
+
+    /// Loads a recording saved at `path` for replay.
+    pub async fn replay(path: impl Into<PathBuf>) -> Result<Self, anyhow::Error> {
+        let path = path.into();
+        let data = tokio::fs::read_to_string(&path)
+            .await
+            .with_context(|| format!("failed to read recording {}", path.display()))?;
+        let file: RecordingFile = serde_json::from_str(&data)
+            .with_context(|| format!("invalid recording {}", path.display()))?;
+        Ok(Self {
+            mode: RecorderMode::Replay,
+            path,
+            interactions: Mutex::new(file.interactions),
+        })
+    }
+
+    pub fn mode(&self) -> RecorderMode {
+        self.mode
+    }
+
+    /// Writes everything recorded so far to the recording's path.
+    pub async fn save(&self) -> Result<(), anyhow::Error> {
+        let file = RecordingFile {
+            interactions: self.interactions.lock().unwrap().clone(),
+        };
+        tokio::fs::write(&self.path, serde_json::to_string_pretty(&file)?)
+            .await
+            .with_context(|| format!("failed to write recording {}", self.path.display()))
+    }
+
+    pub(crate) fn capture(&self, fingerprint: &serde_json::Value, response: &str) {
+        self.interactions
+            .lock()
+            .unwrap()
+            .entry(fingerprint.to_string())
+            .or_default()
+            .push_back(response.to_string());
+    }
+
+    /// Returns the next recorded response for `fingerprint`.
+    pub(crate) fn next_response(
+        &self,
+        fingerprint: &serde_json::Value,
+    ) -> Result<String, anyhow::Error> {
+        let fingerprint = fingerprint.to_string();
+        self.interactions
+            .lock()
+            .unwrap()
+            .get_mut(&fingerprint)
+            .and_then(VecDeque::pop_front)
+            .with_context(|| format!("no recorded response for {}", fingerprint))
+    }
+}
@@ -0,0 +1,220 @@
+//! Running a machine one state at a time, for debugging configs.
+//!
+//! A [`Stepper`] drives the same state loop as [`StateMachine::run`], pausing
+//! after each state so its buffer and variables can be inspected and the
+//! next state chosen by hand. The deadlock watchdog doesn't run while
+//! stepping, since pauses would look like stalls.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+use crate::state_machine::{RunCursor, RunStatus, StateMachine};
+
+/// A command accepted between steps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StepCommand {
+    /// Run the next state. An empty line means the same.
+    Next,
+    /// Show the response buffer.
+    Buffer,
+    /// Make the named state the next one.
+    Goto(String),
+    /// Show the outputs stored under an action's `output_name`.
+    Vars,
+    /// Stop without running any more states.
+    Quit,
+}
+
+impl FromStr for StepCommand {
+    type Err = anyhow::Error;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            None | Some("next" | "n") => StepCommand::Next,
+            Some("buffer" | "b") => StepCommand::Buffer,
+            Some("goto" | "g") => {
+                let state_key = words
+                    .next()
+                    .ok_or_else(|| anyhow::anyhow!("goto needs a state"))?;
+                StepCommand::Goto(state_key.to_string())
+            }
+            Some("vars" | "v") => StepCommand::Vars,
+            Some("quit" | "q") => StepCommand::Quit,
+            Some(command) => anyhow::bail!("unknown command {}", command),
+        };
+        if let Some(extra) = words.next() {
+            anyhow::bail!("unexpected argument {}", extra);
+        }
+        Ok(command)
+    }
+}
+
+/// A run advanced one state per [`step`](Self::step).
+pub struct Stepper {
+    machine: StateMachine,
+    cursor: RunCursor,
+    status: Option<RunStatus>,
+}
+
+impl Stepper {
+    /// Prepares `machine` to run from its initial state with `initial` as
+    /// the buffer, without running anything yet.
+    pub fn new(mut machine: StateMachine, initial: Vec<String>) -> Result<Self, anyhow::Error> {
+        let cursor = machine.start_stepping(initial)?;
+        Ok(Self {
+            machine,
+            cursor,
+            status: None,
+        })
+    }
+
+    /// The state the next step runs, or `None` once the run has finished.
+    pub fn next_state(&self) -> Option<&str> {
+        match self.status {
+            Some(_) => None,
+            None => Some(&self.cursor.next_state_key),
+        }
+    }
+
+    /// How the run ended, once it has.
+    pub fn status(&self) -> Option<RunStatus> {
+        self.status
+    }
+
+    pub fn buffer(&self) -> &[String] {
+        &self.cursor.response_buffer
+    }
+
+    pub fn vars(&self) -> BTreeMap<String, String> {
+        self.machine.variables()
+    }
+
+    /// Runs the next state, returning how the run ended if it did. Once
+    /// finished, further steps return the same status without running
+    /// anything.
+    pub async fn step(&mut self) -> Result<Option<RunStatus>, anyhow::Error> {
+        if self.status.is_some() {
+            return Ok(self.status);
+        }
+        if let Some(status) = self.machine.run_next_state(&mut self.cursor).await? {
+            self.machine
+                .finish_run(status, &self.cursor.response_buffer);
+            self.status = Some(status);
+        }
+        Ok(self.status)
+    }
+
+    /// Makes `state_key` the next state instead of the one the last state
+    /// chose.
+    pub fn goto(&mut self, state_key: &str) -> Result<(), anyhow::Error> {
+        if let Some(status) = self.status {
+            anyhow::bail!("run already finished: {:?}", status);
+        }
+        if !self.machine.has_state(state_key) {
+            anyhow::bail!("state {} not found", state_key);
+        }
+        self.cursor.next_state_key = state_key.to_string();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Action, ActionConfig, AgentConfig, Config};
+    use std::collections::HashMap;
+
+    fn three_states() -> Config {
+        let state = |output: &str, next_state: Option<&str>| AgentConfig {
+            actions: vec![ActionConfig {
+                output_name: Some(output.to_string()),
+                ..Action::Delay {
+                    duration_ms: 0,
+                    output: Some(output.to_string()),
+                }
+                .into()
+            }],
+            next_state: next_state.map(str::to_string),
+            ..Default::default()
+        };
+        Config {
+            initial_state_key: "one".to_string(),
+            states: HashMap::from([
+                ("one".to_string(), state("first", Some("two"))),
+                ("two".to_string(), state("second", Some("three"))),
+                ("three".to_string(), state("third", None)),
+            ]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_step_commands() {
+        assert_eq!("next".parse::<StepCommand>().unwrap(), StepCommand::Next);
+        assert_eq!("".parse::<StepCommand>().unwrap(), StepCommand::Next);
+        assert_eq!(
+            " buffer ".parse::<StepCommand>().unwrap(),
+            StepCommand::Buffer
+        );
+        assert_eq!(
+            "goto three".parse::<StepCommand>().unwrap(),
+            StepCommand::Goto("three".to_string())
+        );
+        assert_eq!("vars".parse::<StepCommand>().unwrap(), StepCommand::Vars);
+        assert_eq!("q".parse::<StepCommand>().unwrap(), StepCommand::Quit);
+    }
+
+    #[test]
+    fn test_parse_step_command_errors() {
+        let err = |line: &str| line.parse::<StepCommand>().unwrap_err().to_string();
+        assert_eq!(err("jump"), "unknown command jump");
+        assert_eq!(err("goto"), "goto needs a state");
+        assert_eq!(err("next now"), "unexpected argument now");
+    }
+
+    #[tokio::test]
+    async fn test_step_runs_one_state_at_a_time() {
+        let machine = StateMachine::new_with_config(three_states()).unwrap();
+        let mut stepper = Stepper::new(machine, vec!["start".to_string()]).unwrap();
+        assert_eq!(stepper.next_state(), Some("one"));
+        assert_eq!(stepper.buffer(), ["start"]);
+
+        assert_eq!(stepper.step().await.unwrap(), None);
+        assert_eq!(stepper.next_state(), Some("two"));
+        assert_eq!(stepper.buffer(), ["first"]);
+        assert_eq!(stepper.vars().keys().collect::<Vec<_>>(), ["first"]);
+
+        assert_eq!(stepper.step().await.unwrap(), None);
+        assert_eq!(stepper.step().await.unwrap(), Some(RunStatus::Completed));
+        assert_eq!(stepper.next_state(), None);
+        assert_eq!(stepper.buffer(), ["third"]);
+        // finished runs stay finished
+        assert_eq!(stepper.step().await.unwrap(), Some(RunStatus::Completed));
+    }
+
+    #[tokio::test]
+    async fn test_goto_overrides_next_state() {
+        let machine = StateMachine::new_with_config(three_states()).unwrap();
+        let mut stepper = Stepper::new(machine, Vec::new()).unwrap();
+
+        stepper.step().await.unwrap();
+        stepper.goto("three").unwrap();
+        assert_eq!(stepper.next_state(), Some("three"));
+        assert_eq!(stepper.step().await.unwrap(), Some(RunStatus::Completed));
+        assert!(!stepper.vars().contains_key("second"));
+
+        let err = stepper.goto("one").unwrap_err().to_string();
+        assert!(err.contains("already finished"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn test_goto_rejects_unknown_state() {
+        let machine = StateMachine::new_with_config(three_states()).unwrap();
+        let mut stepper = Stepper::new(machine, Vec::new()).unwrap();
+
+        let err = stepper.goto("missing").unwrap_err().to_string();
+        assert_eq!(err, "state missing not found");
+        assert_eq!(stepper.next_state(), Some("one"));
+    }
+}
@@ -0,0 +1,218 @@
+//! A mock HTTP server for testing configs whose actions call APIs, and a
+//! clock for testing time-based configs without waiting.
+//!
+//! Enabled by the `test-utils` feature, and always available to the crate's
+//! own tests.
+//!
+//! ```no_run
+//! # async fn example() {
+//! use dynamic_state_machine::test_utils::MockApi;
+//!
+//! let api = MockApi::start().await;
+//! api.respond("GET", "/weather/tokyo", 200, "sunny").await;
+//! // ... run a machine calling `api.url("/weather/tokyo")` ...
+//! api.assert_requested("GET", "/weather/tokyo").await;
+//! # }
+//! ```
+
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::time::Instant;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, Request, ResponseTemplate};
+
+use crate::clock::Clock;
+
+pub use wiremock;
+
+/// A local HTTP server answering with canned responses and recording every
+/// request it receives. It shuts down when dropped.
+pub struct MockApi {
+    server: MockServer,
+}
+
+impl MockApi {
+    pub async fn start() -> Self {
+        Self {
+            server: MockServer::start().await,
+        }
+    }
+
+    /// The server's base URL, e.g. for [`HttpClientConfig::base_url`].
+    ///
+    /// [`HttpClientConfig::base_url`]: crate::config::HttpClientConfig::base_url
+    pub fn uri(&self) -> String {
+        self.server.uri()
+    }
+
+    /// The absolute URL of `path` on this server.
+    pub fn url(&self, path: &str) -> String {
+        format!("{}{}", self.server.uri(), path)
+    }
+
+    /// Answers `method` requests to `path` with `status` and `body`.
+    pub async fn respond(&self, method: &str, path: &str, status: u16, body: impl Into<String>) {
+        self.mount(
+            method,
+            path,
+            ResponseTemplate::new(status).set_body_string(body.into()),
+        )
+        .await;
+    }
+
+    /// Answers `method` requests to `path` with a 200 JSON `body`.
+    pub async fn respond_json(&self, method: &str, path: &str, body: serde_json::Value) {
+        self.mount(method, path, ResponseTemplate::new(200).set_body_json(body))
+            .await;
+    }
+
+    /// Answers `method` requests to `path` with `response`, for responses
+    /// needing headers or delays.
+    pub async fn mount(&self, method_name: &str, path_value: &str, response: ResponseTemplate) {
+        Mock::given(method(method_name))
+            .and(path(path_value))
+            .respond_with(response)
+            .mount(&self.server)
+            .await;
+    }
+
+    /// Every request received so far, in arrival order.
+    pub async fn requests(&self) -> Vec<Request> {
+        self.server.received_requests().await.unwrap_or_default()
+    }
+
+    /// Requests received so far for `method` and `path`.
+    pub async fn requests_to(&self, method: &str, path: &str) -> Vec<Request> {
+        self.requests()
+            .await
+            .into_iter()
+            .filter(|request| {
+                request.method.as_str().eq_ignore_ascii_case(method) && request.url.path() == path
+            })
+            .collect()
+    }
+
+    /// Returns the first request received for `method` and `path`, panicking
+    /// with the requests actually received if there was none.
+    pub async fn assert_requested(&self, method: &str, path: &str) -> Request {
+        let requests = self.requests_to(method, path).await;
+        match requests.into_iter().next() {
+            Some(request) => request,
+            None => {
+                let received: Vec<String> = self
+                    .requests()
+                    .await
+                    .iter()
+                    .map(|request| format!("{} {}", request.method, request.url.path()))
+                    .collect();
+                panic!(
+                    "expected a {} {} request, received {:?}",
+                    method, path, received
+                );
+            }
+        }
+    }
+
+    /// The underlying server, for mocks the helpers don't cover.
+    pub fn server(&self) -> &MockServer {
+        &self.server
+    }
+}
+
+/// A clock that only moves when told to, or when every task is waiting on
+/// it, for [`StateMachine::with_clock`].
+///
+/// It pauses Tokio's clock, so it must be started inside a current-thread
+/// runtime (the default for `#[tokio::test]`), and it affects everything in
+/// that runtime, including HTTP client timeouts. While paused, a runtime
+/// with nothing to do jumps straight to its next timer, so an hour-long
+/// Delay finishes at once.
+///
+/// [`StateMachine::with_clock`]: crate::state_machine::StateMachine::with_clock
+#[derive(Debug)]
+pub struct TestClock {
+    _private: (),
+}
+
+impl TestClock {
+    pub fn start() -> Self {
+        tokio::time::pause();
+        Self { _private: () }
+    }
+
+    /// Moves the clock forward by `duration`, firing every timer due by then.
+    pub async fn advance(&self, duration: Duration) {
+        tokio::time::advance(duration).await;
+    }
+}
+
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Action, AgentConfig, Config};
+    use crate::models::{CallApiData, HttpMethod};
+    use crate::state_machine::StateMachine;
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_mock_api_answers_call_api() {
+        let api = MockApi::start().await;
+        api.respond_json(
+            "POST",
+            "/orders",
+            serde_json::json!({ "id": 42, "status": "accepted" }),
+        )
+        .await;
+
+        let config = Config {
+            label: "orders".to_string(),
+            initial_state_key: "order".to_string(),
+            states: HashMap::from([(
+                "order".to_string(),
+                AgentConfig {
+                    actions: vec![Action::CallApi(CallApiData {
+                        url: api.url("/orders"),
+                        auth_header_name: "Authorization".to_string(),
+                        auth_header_value: "Bearer token".to_string(),
+                        method: HttpMethod::POST,
+                        body: Some(r#"{"item":"tea"}"#.to_string()),
+                        ..Default::default()
+                    })
+                    .into()],
+                    next_state: None,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let output = StateMachine::new_with_config(config)
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+
+        let response: serde_json::Value = serde_json::from_str(&output[0]).unwrap();
+        assert_eq!(response["status"], "accepted");
+        let request = api.assert_requested("POST", "/orders").await;
+        assert_eq!(request.body, br#"{"item":"tea"}"#);
+        assert_eq!(request.headers["authorization"], "Bearer token");
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "expected a GET /missing request")]
+    async fn test_assert_requested_panics_without_request() {
+        let api = MockApi::start().await;
+        api.assert_requested("GET", "/missing").await;
+    }
+}
@@ -0,0 +1,173 @@
+//! Capping how much of an action's output enters the response buffer, so
+//! large LLM and API responses passed through many states stay within
+//! downstream limits such as an LLM's context.
+//!
+//! A config's `max_output` applies to every action; an action's own
+//! `max_output` replaces it. Outputs stored under an `output_name` are kept
+//! whole, so `Var` placeholders can still reach into them.
+
+use serde::{Deserialize, Serialize};
+
+/// Marks the characters [`Truncation::Middle`] removes.
+pub const ELLIPSIS: &str = "…";
+
+/// The longest output kept, and which part of a longer one.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutputLimit {
+    /// Counted in characters, including any ellipsis.
+    pub max_chars: usize,
+    #[serde(default)]
+    pub truncate: Truncation,
+}
+
+/// Which part of an output longer than its limit is kept.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Truncation {
+    /// The start.
+    #[default]
+    Head,
+    /// The end, such as the last lines of a log.
+    Tail,
+    /// The start and the end, joined by an [`ELLIPSIS`].
+    Middle,
+}
+
+impl OutputLimit {
+    /// `output` cut down to `max_chars`, or unchanged if it fits.
+    pub fn apply(&self, output: String) -> String {
+        let chars = output.chars().count();
+        if chars <= self.max_chars {
+            return output;
+        }
+        match self.truncate {
+            Truncation::Head => output.chars().take(self.max_chars).collect(),
+            Truncation::Tail => output.chars().skip(chars - self.max_chars).collect(),
+            Truncation::Middle => {
+                let Some(kept) = self.max_chars.checked_sub(ELLIPSIS.chars().count()) else {
+                    return output.chars().take(self.max_chars).collect();
+                };
+                // an odd character goes to the start
+                let head = kept - kept / 2;
+                let tail = kept / 2;
+                let mut truncated: String = output.chars().take(head).collect();
+                truncated.push_str(ELLIPSIS);
+                truncated.extend(output.chars().skip(chars - tail));
+                truncated
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Action, ActionConfig, AgentConfig, BufferMode, Config};
+    use crate::state_machine::StateMachine;
+    use std::collections::HashMap;
+
+    fn limit(max_chars: usize, truncate: Truncation) -> OutputLimit {
+        OutputLimit {
+            max_chars,
+            truncate,
+        }
+    }
+
+    const ALPHABET: &str = "abcdefghijklmnopqrstuvwxyz";
+
+    #[test]
+    fn test_head_keeps_start() {
+        let truncated = limit(5, Truncation::Head).apply(ALPHABET.to_string());
+        assert_eq!(truncated, "abcde");
+    }
+
+    #[test]
+    fn test_tail_keeps_end() {
+        let truncated = limit(5, Truncation::Tail).apply(ALPHABET.to_string());
+        assert_eq!(truncated, "vwxyz");
+    }
+
+    #[test]
+    fn test_middle_keeps_both_ends() {
+        let truncated = limit(8, Truncation::Middle).apply(ALPHABET.to_string());
+        assert_eq!(truncated, "abcd…xyz");
+        assert_eq!(truncated.chars().count(), 8);
+
+        let truncated = limit(7, Truncation::Middle).apply(ALPHABET.to_string());
+        assert_eq!(truncated, "abc…xyz");
+        assert_eq!(
+            limit(1, Truncation::Middle).apply(ALPHABET.to_string()),
+            "…"
+        );
+        assert_eq!(limit(0, Truncation::Middle).apply(ALPHABET.to_string()), "");
+    }
+
+    #[test]
+    fn test_short_outputs_and_multibyte_characters() {
+        for truncate in [Truncation::Head, Truncation::Tail, Truncation::Middle] {
+            let limit = limit(26, truncate);
+            assert_eq!(limit.apply(ALPHABET.to_string()), ALPHABET);
+        }
+        // counts characters, never splitting one
+        let truncated = limit(4, Truncation::Middle).apply("ünïcödé".to_string());
+        assert_eq!(truncated, "ün…é");
+        assert_eq!(
+            limit(3, Truncation::Tail).apply("日本語です".to_string()),
+            "語です"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_action_limit_overrides_config_limit() {
+        let output = |output: &str| Action::Delay {
+            duration_ms: 0,
+            output: Some(output.to_string()),
+        };
+        let state = |actions: Vec<ActionConfig>, next_state: Option<&str>| AgentConfig {
+            actions,
+            next_state: next_state.map(str::to_string),
+            ..Default::default()
+        };
+        let config = Config {
+            label: "limits".to_string(),
+            initial_state_key: "run".to_string(),
+            states: HashMap::from([
+                (
+                    "run".to_string(),
+                    state(
+                        vec![
+                            output(ALPHABET).into(),
+                            ActionConfig {
+                                max_output: Some(limit(10, Truncation::Middle)),
+                                output_name: Some("letters".to_string()),
+                                ..output(ALPHABET).into()
+                            },
+                            output("short").into(),
+                        ],
+                        Some("stored"),
+                    ),
+                ),
+                (
+                    "stored".to_string(),
+                    state(
+                        vec![ActionConfig {
+                            max_output: Some(limit(100, Truncation::Head)),
+                            ..output(r#"{"Named":"letters"}"#).into()
+                        }],
+                        None,
+                    ),
+                ),
+            ]),
+            buffer_mode: BufferMode::Append,
+            max_output: Some(limit(6, Truncation::Tail)),
+            ..Default::default()
+        };
+        let output = StateMachine::new_with_config(config)
+            .unwrap()
+            .run()
+            .await
+            .unwrap();
+        // the output stored under `letters` is whole
+        assert_eq!(output, ["uvwxyz", "abcde…wxyz", "short", ALPHABET]);
+    }
+}
@@ -0,0 +1,678 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::config::{Action, AgentConfig, Config, DuplicateOutputLabels};
+use crate::state_machine::{
+    env_placeholders, invalid_placeholders, var_placeholders, LAST_STATUS_VAR,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The config runs, but probably not as intended.
+    Warning,
+    /// The config can't run as written.
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub kind: IssueKind,
+    /// State the issue was found in, if it belongs to one.
+    pub state: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    MissingInitialState,
+    DanglingTransition,
+    UnreachableState,
+    DuplicateStreamLabel,
+    MalformedPlaceholder,
+    UnknownActionHandler,
+    /// A placeholder reads a variable kept only for older configs.
+    DeprecatedVariable,
+}
+
+impl std::fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.state {
+            Some(state) => write!(f, "{}: state {}: {}", self.severity, state, self.message),
+            None => write!(f, "{}: {}", self.severity, self.message),
+        }
+    }
+}
+
+impl ValidationIssue {
+    fn new(severity: Severity, kind: IssueKind, state: Option<&str>, message: String) -> Self {
+        Self {
+            severity,
+            kind,
+            state: state.map(str::to_string),
+            message,
+        }
+    }
+}
+
+/// Runs every static check on `config` without executing it, returning all
+/// issues found ordered by state key.
+///
+/// Unlike [`Config::validate`], which stops at the first error, this also
+/// reports warnings: states no transition reaches, placeholders that would
+/// resolve to an empty string and reads of deprecated variables.
+pub fn validate_config(config: &Config) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let mut state_keys: Vec<&String> = config.states.keys().collect();
+    state_keys.sort();
+
+    if !config.states.contains_key(&config.initial_state_key) {
+        issues.push(ValidationIssue::new(
+            Severity::Error,
+            IssueKind::MissingInitialState,
+            None,
+            format!("initial state {} not found", config.initial_state_key),
+        ));
+    }
+    if let Some(dead_letter) = &config.dead_letter_state {
+        if !config.states.contains_key(dead_letter) {
+            issues.push(ValidationIssue::new(
+                Severity::Error,
+                IssueKind::DanglingTransition,
+                None,
+                format!("dead-letter state {} not found", dead_letter),
+            ));
+        }
+    }
+
+    let mut entry_points: Vec<(&String, &String)> = config.entry_points.iter().collect();
+    entry_points.sort();
+    for (entry, state_key) in entry_points {
+        if !config.states.contains_key(state_key) {
+            issues.push(ValidationIssue::new(
+                Severity::Error,
+                IssueKind::DanglingTransition,
+                None,
+                format!("entry point {} names missing state {}", entry, state_key),
+            ));
+        }
+    }
+
+    let placeholder_regex = config.placeholder_delimiters.regex();
+    for &state_key in &state_keys {
+        let state = &config.states[state_key];
+        if let Some(next_state) = &state.next_state {
+            if !config.placeholder_delimiters.is_templated(next_state)
+                && !config.states.contains_key(next_state)
+            {
+                issues.push(ValidationIssue::new(
+                    Severity::Error,
+                    IssueKind::DanglingTransition,
+                    Some(state_key),
+                    format!("next state {} not found", next_state),
+                ));
+            }
+        }
+
+        let reads_last_status = state_templates(state).into_iter().any(|template| {
+            var_placeholders(template, &placeholder_regex)
+                .iter()
+                .any(|name| name == LAST_STATUS_VAR)
+        });
+        if reads_last_status {
+            issues.push(ValidationIssue::new(
+                Severity::Warning,
+                IssueKind::DeprecatedVariable,
+                Some(state_key),
+                format!(
+                    "{} is deprecated; capture the status with {{\"from\": \"status\", \"into\": ...}}",
+                    LAST_STATUS_VAR
+                ),
+            ));
+        }
+
+        for template in state_templates(state) {
+            for placeholder in invalid_placeholders(template, &placeholder_regex) {
+                issues.push(ValidationIssue::new(
+                    Severity::Error,
+                    IssueKind::MalformedPlaceholder,
+                    Some(state_key),
+                    format!(
+                        "invalid placeholder {}{}{} in {:?}",
+                        config.placeholder_delimiters.open,
+                        placeholder,
+                        config.placeholder_delimiters.close,
+                        template
+                    ),
+                ));
+            }
+        }
+    }
+
+    // a shared label is intentional
+    if config.duplicate_output_labels == DuplicateOutputLabels::Error {
+        for (label, states) in duplicate_output_labels(config) {
+            issues.push(ValidationIssue::new(
+                Severity::Error,
+                IssueKind::DuplicateStreamLabel,
+                None,
+                format!(
+                    "output stream {} is written by {} agents (states {})",
+                    label,
+                    states.len(),
+                    states.join(", ")
+                ),
+            ));
+        }
+    }
+
+    if let Some(reachable) = reachable_states(config) {
+        for &state_key in &state_keys {
+            if !reachable.contains(state_key.as_str()) {
+                issues.push(ValidationIssue::new(
+                    Severity::Warning,
+                    IssueKind::UnreachableState,
+                    Some(state_key),
+                    "not reachable from the initial state".to_string(),
+                ));
+            }
+        }
+    }
+
+    issues
+}
+
+/// Custom actions naming a handler that isn't in `registered`, one issue per
+/// state and handler, ordered by state key.
+pub fn validate_action_handlers(
+    config: &Config,
+    registered: &HashSet<&str>,
+) -> Vec<ValidationIssue> {
+    let mut state_keys: Vec<&String> = config.states.keys().collect();
+    state_keys.sort();
+    let mut issues = Vec::new();
+    for state_key in state_keys {
+        let mut unknown: Vec<&str> = config.states[state_key]
+            .actions
+            .iter()
+            .flat_map(|action_config| action_config.action.flatten())
+            .filter_map(|action| match action {
+                Action::Custom { handler, .. } if !registered.contains(handler.as_str()) => {
+                    Some(handler.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        unknown.sort();
+        unknown.dedup();
+        for handler in unknown {
+            issues.push(ValidationIssue::new(
+                Severity::Error,
+                IssueKind::UnknownActionHandler,
+                Some(state_key),
+                format!("no custom action handler {} is registered", handler),
+            ));
+        }
+    }
+    issues
+}
+
+/// Variables required by the config's `env_check` that are unset: every
+/// `required` one, and in `strict` mode every one an `Env` placeholder
+/// reads. Sorted and deduplicated.
+pub fn missing_env_vars(config: &Config) -> Vec<String> {
+    let mut names = config.env_check.required.clone();
+    if config.env_check.strict {
+        let placeholder_regex = config.placeholder_delimiters.regex();
+        for state in config.states.values() {
+            for template in state_templates(state) {
+                names.extend(env_placeholders(template, &placeholder_regex));
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names.retain(|name| std::env::var_os(name).is_none());
+    names
+}
+
+/// The first placeholder in `config` that doesn't parse, with the path of
+/// the field holding it, such as `states.fetch.actions[0].call_api.url`.
+/// States are checked in key order.
+pub(crate) fn first_malformed_placeholder(config: &Config) -> Option<(String, String)> {
+    let placeholder_regex = config.placeholder_delimiters.regex();
+    let mut state_keys: Vec<&String> = config.states.keys().collect();
+    state_keys.sort();
+    state_keys.into_iter().find_map(|state_key| {
+        state_template_fields(&config.states[state_key])
+            .into_iter()
+            .find_map(|(path, template)| {
+                let placeholder = invalid_placeholders(template, &placeholder_regex)
+                    .into_iter()
+                    .next()?;
+                Some((format!("states.{}.{}", state_key, path), placeholder))
+            })
+    })
+}
+
+/// Output labels named by more than one SpawnAgent action, with the states
+/// of those actions (repeated for several in one state), sorted by label.
+pub(crate) fn duplicate_output_labels(config: &Config) -> Vec<(&str, Vec<&str>)> {
+    let mut state_keys: Vec<&String> = config.states.keys().collect();
+    state_keys.sort();
+    let mut output_labels: HashMap<&str, Vec<&str>> = HashMap::new();
+    for state_key in state_keys {
+        for action in config.states[state_key]
+            .actions
+            .iter()
+            .flat_map(|config| config.action.flatten())
+        {
+            if let Action::SpawnAgent { agent_data } = action {
+                output_labels
+                    .entry(&agent_data.output_label)
+                    .or_default()
+                    .push(state_key);
+            }
+        }
+    }
+    let mut duplicates: Vec<_> = output_labels
+        .into_iter()
+        .filter(|(_, states)| states.len() > 1)
+        .collect();
+    duplicates.sort();
+    duplicates
+}
+
+/// Templates of a state that are resolved through `process_placeholders`.
+fn state_templates(state: &AgentConfig) -> Vec<&str> {
+    state_template_fields(state)
+        .into_iter()
+        .map(|(_, template)| template)
+        .collect()
+}
+
+/// Like [`state_templates`], each with the path of its field within the
+/// state.
+fn state_template_fields(state: &AgentConfig) -> Vec<(String, &str)> {
+    let mut fields = Vec::new();
+    if let Some(next_state) = &state.next_state {
+        fields.push(("next_state".to_string(), next_state.as_str()));
+    }
+    if let Some(guard) = &state.guard {
+        fields.push(("guard".to_string(), guard.as_str()));
+    }
+    for (index, action_config) in state.actions.iter().enumerate() {
+        action_template_fields(
+            &action_config.action,
+            &format!("actions[{}]", index),
+            &mut fields,
+        );
+    }
+    fields
+}
+
+fn action_template_fields<'a>(action: &'a Action, path: &str, fields: &mut Vec<(String, &'a str)>) {
+    let mut push = |field: &str, template: &'a str| {
+        fields.push((format!("{}.{}", path, field), template));
+    };
+    match action {
+        Action::CallApi(data) => {
+            push("call_api.url", &data.url);
+            push("call_api.auth_header_value", &data.auth_header_value);
+            let mut headers: Vec<_> = data.headers.iter().collect();
+            headers.sort();
+            for (name, value) in headers {
+                push(&format!("call_api.headers.{}", name), value);
+            }
+            if let Some(user_agent) = &data.user_agent {
+                push("call_api.user_agent", user_agent);
+            }
+            if let Some(body_file) = &data.body_file {
+                push("call_api.body_file", body_file);
+            }
+            if let Some(save_to) = &data.save_to {
+                push("call_api.save_to", save_to);
+            }
+            if let Some(signing) = &data.signing {
+                push("call_api.signing.secret", &signing.secret);
+            }
+        }
+        Action::Sse(data) => {
+            push("sse.url", &data.url);
+            let mut headers: Vec<_> = data.headers.iter().collect();
+            headers.sort();
+            for (name, value) in headers {
+                push(&format!("sse.headers.{}", name), value);
+            }
+        }
+        Action::Llm(data) => {
+            push("llm.user_prompt", &data.user_prompt);
+            if let Some(system_prompt) = &data.system_prompt {
+                push("llm.system_prompt", system_prompt);
+            }
+        }
+        Action::MapAgent(data) => {
+            for (index, input) in data.inputs.iter().enumerate() {
+                push(&format!("map_agent.inputs[{}]", index), input);
+            }
+        }
+        Action::CallMachine(data) => {
+            if let Some(input) = &data.input {
+                push("call_machine.input", input);
+            }
+        }
+        Action::Delay {
+            output: Some(output),
+            ..
+        } => push("delay.output", output),
+        Action::CancelAgent { label } => push("cancel_agent.label", label),
+        Action::Terminate { status, message } => {
+            if let Some(status) = status {
+                push("terminate.status", status);
+            }
+            if let Some(message) = message {
+                push("terminate.message", message);
+            }
+        }
+        Action::Assert {
+            actual, expected, ..
+        } => {
+            push("assert.actual", actual);
+            push("assert.expected", expected);
+        }
+        Action::WithTimeout { action, .. } => {
+            action_template_fields(action, &format!("{}.with_timeout.action", path), fields);
+        }
+        Action::Race { actions } => {
+            for (index, action) in actions.iter().enumerate() {
+                let path = format!("{}.race.actions[{}]", path, index);
+                action_template_fields(action, &path, fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// States reachable from the initial state, entry points and dead-letter
+/// state through literal transitions, or `None` when a reachable state has a
+/// templated `next_state` and could lead anywhere.
+fn reachable_states(config: &Config) -> Option<HashSet<&str>> {
+    let mut reachable = HashSet::new();
+    let mut queue: VecDeque<&str> = std::iter::once(config.initial_state_key.as_str())
+        .chain(config.entry_points.values().map(String::as_str))
+        .chain(config.dead_letter_state.as_deref())
+        .collect();
+    while let Some(state_key) = queue.pop_front() {
+        let Some(state) = config.states.get(state_key) else {
+            continue;
+        };
+        if !reachable.insert(state_key) {
+            continue;
+        }
+        if let Some(next_state) = &state.next_state {
+            if config.placeholder_delimiters.is_templated(next_state) {
+                return None;
+            }
+            queue.push_back(next_state);
+        }
+    }
+    Some(reachable)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ActionConfig;
+    use crate::models::{AgentConfigSource, AgentData, CallApiData};
+
+    fn state(next_state: Option<&str>, actions: Vec<Action>) -> AgentConfig {
+        AgentConfig {
+            actions: actions.into_iter().map(ActionConfig::from).collect(),
+            next_state: next_state.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn config_with(initial_state_key: &str, states: Vec<(&str, AgentConfig)>) -> Config {
+        Config {
+            initial_state_key: initial_state_key.to_string(),
+            states: states
+                .into_iter()
+                .map(|(key, state)| (key.to_string(), state))
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn kinds(issues: &[ValidationIssue]) -> Vec<(Severity, IssueKind)> {
+        issues
+            .iter()
+            .map(|issue| (issue.severity, issue.kind))
+            .collect()
+    }
+
+    fn spawn_agent(output_label: &str) -> Action {
+        Action::SpawnAgent {
+            agent_data: AgentData {
+                config_source: AgentConfigSource::File {
+                    agent_config_file: "agent_config.json".to_string(),
+                },
+                input_label: "input".to_string(),
+                output_label: output_label.to_string(),
+                is_background: false,
+                ..Default::default()
+            },
+        }
+    }
+
+    #[test]
+    fn test_valid_config_has_no_issues() {
+        let config = config_with(
+            "start",
+            vec![
+                ("start", state(Some("end"), vec![])),
+                ("end", state(None, vec![])),
+            ],
+        );
+        assert!(validate_config(&config).is_empty());
+    }
+
+    #[test]
+    fn test_missing_initial_state() {
+        let config = config_with("missing", vec![("start", state(None, vec![]))]);
+        let issues = validate_config(&config);
+        assert!(kinds(&issues).contains(&(Severity::Error, IssueKind::MissingInitialState)));
+    }
+
+    #[test]
+    fn test_dangling_transition() {
+        let mut config = config_with("start", vec![("start", state(Some("nowhere"), vec![]))]);
+        config.dead_letter_state = Some("also_nowhere".to_string());
+        let issues = validate_config(&config);
+        assert_eq!(
+            kinds(&issues),
+            vec![
+                (Severity::Error, IssueKind::DanglingTransition),
+                (Severity::Error, IssueKind::DanglingTransition),
+            ]
+        );
+        assert_eq!(issues[1].state.as_deref(), Some("start"));
+    }
+
+    #[test]
+    fn test_unreachable_state() {
+        let config = config_with(
+            "start",
+            vec![
+                ("start", state(None, vec![])),
+                ("orphan", state(Some("start"), vec![])),
+            ],
+        );
+        let issues = validate_config(&config);
+        assert_eq!(
+            kinds(&issues),
+            vec![(Severity::Warning, IssueKind::UnreachableState)]
+        );
+        assert_eq!(issues[0].state.as_deref(), Some("orphan"));
+
+        // a templated transition could lead to any state
+        let config = config_with(
+            "start",
+            vec![
+                ("start", state(Some("{Output}"), vec![])),
+                ("orphan", state(None, vec![])),
+            ],
+        );
+        assert!(validate_config(&config).is_empty());
+    }
+
+    #[test]
+    fn test_entry_points() {
+        let mut config = config_with(
+            "start",
+            vec![
+                ("start", state(None, vec![])),
+                ("report", state(None, vec![])),
+            ],
+        );
+        config.entry_points = HashMap::from([
+            ("report".to_string(), "report".to_string()),
+            ("broken".to_string(), "missing".to_string()),
+        ]);
+        let issues = validate_config(&config);
+        // "report" is reachable through its entry point
+        assert_eq!(
+            kinds(&issues),
+            vec![(Severity::Error, IssueKind::DanglingTransition)]
+        );
+        assert!(issues[0].message.contains("broken"), "{}", issues[0]);
+    }
+
+    #[test]
+    fn test_duplicate_stream_label() {
+        let config = config_with(
+            "start",
+            vec![
+                ("start", state(Some("next"), vec![spawn_agent("results")])),
+                ("next", state(None, vec![spawn_agent("results")])),
+            ],
+        );
+        let issues = validate_config(&config);
+        assert_eq!(
+            kinds(&issues),
+            vec![(Severity::Error, IssueKind::DuplicateStreamLabel)]
+        );
+        assert_eq!(
+            issues[0].message,
+            "output stream results is written by 2 agents (states next, start)"
+        );
+
+        let shared = Config {
+            duplicate_output_labels: DuplicateOutputLabels::Share,
+            ..config
+        };
+        assert!(validate_config(&shared).is_empty());
+    }
+
+    #[test]
+    fn test_malformed_placeholder() {
+        let config = config_with(
+            "start",
+            vec![(
+                "start",
+                state(
+                    None,
+                    vec![Action::CallApi(CallApiData {
+                        url: "http://localhost/{Input}/{Inptu}".to_string(),
+                        ..Default::default()
+                    })],
+                ),
+            )],
+        );
+        let issues = validate_config(&config);
+        assert_eq!(
+            kinds(&issues),
+            vec![(Severity::Error, IssueKind::MalformedPlaceholder)]
+        );
+        assert!(issues[0].message.contains("{Inptu}"), "{}", issues[0]);
+    }
+
+    #[test]
+    fn test_first_malformed_placeholder_names_field() {
+        let race = Action::Race {
+            actions: vec![
+                Action::NoOp,
+                Action::Delay {
+                    duration_ms: 0,
+                    output: Some("{Input} {\"Env\":}".to_string()),
+                },
+            ],
+        };
+        let config = config_with(
+            "start",
+            vec![
+                ("start", state(Some("{Output}"), vec![race])),
+                ("zzz", state(Some("{Nope}"), vec![])),
+            ],
+        );
+        assert_eq!(
+            first_malformed_placeholder(&config),
+            Some((
+                "states.start.actions[0].race.actions[1].delay.output".to_string(),
+                r#""Env":"#.to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_unknown_action_handler() {
+        let custom = |handler: &str| Action::Custom {
+            handler: handler.to_string(),
+            params: serde_json::Value::Null,
+        };
+        let wrapped = Action::Race {
+            actions: vec![custom("nested"), custom("known")],
+        };
+        let config = config_with(
+            "start",
+            vec![("start", state(None, vec![custom("missing"), wrapped]))],
+        );
+        let issues = validate_action_handlers(&config, &HashSet::from(["known"]));
+        assert_eq!(
+            kinds(&issues),
+            vec![(Severity::Error, IssueKind::UnknownActionHandler); 2]
+        );
+        assert!(issues[0].message.contains("missing"), "{}", issues[0]);
+        assert!(issues[1].message.contains("nested"), "{}", issues[1]);
+        assert!(
+            validate_action_handlers(&config, &HashSet::from(["known", "missing", "nested"]))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_last_status_is_deprecated() {
+        let routed = AgentConfig {
+            guard: Some(r#"{"Var":"status"}"#.to_string()),
+            ..state(Some(r#"status_{"Var":"last_status"}"#), vec![])
+        };
+        let config = config_with(
+            "start",
+            vec![("start", routed), ("status_200", state(None, vec![]))],
+        );
+        let issues = validate_config(&config);
+        assert_eq!(
+            kinds(&issues),
+            vec![(Severity::Warning, IssueKind::DeprecatedVariable)]
+        );
+        assert_eq!(issues[0].state.as_deref(), Some("start"));
+    }
+}